@@ -0,0 +1,206 @@
+//! Custom band gain curves loaded from files. A curve is a list of frequencies with a gain for
+//! every frequency, and every band's gain is interpolated from the curve at the band's center
+//! frequency on a logarithmic frequency scale. That way the same curve works for any band count
+//! and band range.
+//!
+//! Two file formats are supported:
+//!
+//! - WAV files holding an impulse response. The curve is the impulse response's magnitude
+//!   response at [`IR_CURVE_POINTS`] logarithmically spaced frequencies, normalized so its
+//!   loudest point is at 0 dB.
+//! - Text files with a frequency in Hz and a gain in dB on every line, separated by whitespace, a
+//!   comma, or a semicolon. Empty lines and lines starting with `#` are skipped.
+
+use serde::{Deserialize, Serialize};
+use std::f64::consts::TAU;
+use std::io;
+use std::path::Path;
+
+/// The number of points the curve for an impulse response is sampled at.
+const IR_CURVE_POINTS: usize = 64;
+/// The range the curve for an impulse response is sampled in. The upper end is lowered for
+/// impulse responses with low sample rates.
+const IR_MIN_FREQUENCY: f64 = 20.0;
+const IR_MAX_FREQUENCY: f64 = 20000.0;
+/// Only this many samples of an impulse response are used. Anything past this is the tail of a
+/// reverb, which doesn't say much about the impulse response's tone.
+const MAX_IR_SAMPLES: usize = 1 << 16;
+/// The lowest gain an impulse response's curve can have, so its notches don't mute bands
+/// entirely.
+const MIN_IR_GAIN_DB: f32 = -60.0;
+
+/// A single point on a band gain curve.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub frequency: f32,
+    pub gain_db: f32,
+}
+
+/// Load a curve from the file at `path`. Files ending in `.wav` are read as impulse responses,
+/// and all other files are read as text. The points are sorted by frequency.
+pub fn load(path: &Path) -> io::Result<Vec<CurvePoint>> {
+    let is_wav = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+    let mut curve = if is_wav {
+        let (samples, sample_rate) = read_wav(&std::fs::read(path)?)?;
+        impulse_response_curve(&samples, sample_rate)
+    } else {
+        parse_text(&std::fs::read_to_string(path)?)?
+    };
+    if curve.is_empty() {
+        return Err(invalid_data("The file doesn't contain a curve"));
+    }
+
+    curve.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+    Ok(curve)
+}
+
+/// The curve's gain in decibels at `frequency`, interpolated on a logarithmic frequency scale. The
+/// gain is held constant past the curve's first and last points, and an empty curve is 0 dB
+/// everywhere. `curve` needs to be sorted by frequency.
+pub fn gain_db_at(curve: &[CurvePoint], frequency: f32) -> f32 {
+    let upper_idx = curve.partition_point(|point| point.frequency < frequency);
+    match (
+        upper_idx.checked_sub(1).map(|idx| &curve[idx]),
+        curve.get(upper_idx),
+    ) {
+        (Some(lower), Some(upper)) => {
+            let t = (frequency / lower.frequency).ln() / (upper.frequency / lower.frequency).ln();
+            lower.gain_db + (upper.gain_db - lower.gain_db) * t
+        }
+        (Some(point), None) | (None, Some(point)) => point.gain_db,
+        (None, None) => 0.0,
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_text(contents: &str) -> io::Result<Vec<CurvePoint>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut values = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>().ok().filter(|value| value.is_finite()));
+            match (values.next(), values.next(), values.next()) {
+                (Some(Some(frequency)), Some(Some(gain_db)), None) if frequency > 0.0 => {
+                    Ok(CurvePoint { frequency, gain_db })
+                }
+                _ => Err(invalid_data(
+                    "Every line needs to contain a positive frequency and a gain",
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Read the first channel of a PCM or floating point WAV file. Returns the samples and the sample
+/// rate.
+fn read_wav(data: &[u8]) -> io::Result<(Vec<f32>, f32)> {
+    if data.get(..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+        return Err(invalid_data("Not a WAV file"));
+    }
+
+    // The format chunk always comes before the data chunk
+    let mut format = None;
+    let mut chunks = &data[12..];
+    while let [id0, id1, id2, id3, s0, s1, s2, s3, rest @ ..] = chunks {
+        let size = u32::from_le_bytes([*s0, *s1, *s2, *s3]) as usize;
+        let body = rest.get(..size).unwrap_or(rest);
+        match &[*id0, *id1, *id2, *id3] {
+            b"fmt " if body.len() >= 16 => {
+                let u16_at = |idx: usize| u16::from_le_bytes([body[idx], body[idx + 1]]);
+                // The extensible format stores the actual format at the start of its GUID
+                let format_tag = match u16_at(0) {
+                    0xFFFE if body.len() >= 26 => u16_at(24),
+                    format_tag => format_tag,
+                };
+                let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]) as f32;
+                format = Some((format_tag, u16_at(2) as usize, sample_rate, u16_at(14)));
+            }
+            b"data" => {
+                let Some((format_tag, num_channels, sample_rate, bits_per_sample)) = format else {
+                    return Err(invalid_data("The WAV file's format chunk is missing"));
+                };
+                let bytes_per_sample = usize::from(bits_per_sample / 8);
+                let decode: fn(&[u8]) -> f32 = match (format_tag, bits_per_sample) {
+                    (1, 16) => {
+                        |bytes| f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0
+                    }
+                    (1, 24) => |bytes| {
+                        (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32
+                            / 8388608.0
+                    },
+                    (1, 32) => |bytes| {
+                        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                            / 2147483648.0
+                    },
+                    (3, 32) => |bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    _ => return Err(invalid_data("Unsupported WAV sample format")),
+                };
+                if num_channels == 0 || sample_rate <= 0.0 {
+                    return Err(invalid_data("Invalid WAV format"));
+                }
+
+                let samples = body
+                    .chunks_exact(bytes_per_sample * num_channels)
+                    .take(MAX_IR_SAMPLES)
+                    .map(decode)
+                    .collect();
+                return Ok((samples, sample_rate));
+            }
+            _ => (),
+        }
+
+        // Chunks are padded to an even number of bytes
+        chunks = rest.get(size + size % 2..).unwrap_or_default();
+    }
+
+    Err(invalid_data("The WAV file doesn't contain any audio"))
+}
+
+/// Compute the magnitude response of the impulse response in `samples`.
+fn impulse_response_curve(samples: &[f32], sample_rate: f32) -> Vec<CurvePoint> {
+    let sample_rate = f64::from(sample_rate);
+    let max_frequency = IR_MAX_FREQUENCY.min(sample_rate * 0.49);
+    let magnitudes: Vec<(f64, f64)> = (0..IR_CURVE_POINTS)
+        .map(|point_idx| {
+            let t = point_idx as f64 / (IR_CURVE_POINTS - 1) as f64;
+            let frequency = IR_MIN_FREQUENCY * (max_frequency / IR_MIN_FREQUENCY).powf(t);
+
+            // The DFT at a single frequency
+            let omega = TAU * frequency / sample_rate;
+            let (re, im) = samples.iter().enumerate().fold(
+                (0.0, 0.0),
+                |(re, im): (f64, f64), (sample_idx, sample)| {
+                    let (sin, cos) = (omega * sample_idx as f64).sin_cos();
+                    let sample = f64::from(*sample);
+                    (re + sample * cos, im - sample * sin)
+                },
+            );
+
+            (frequency, re.hypot(im))
+        })
+        .collect();
+
+    let peak = magnitudes
+        .iter()
+        .fold(0.0, |peak: f64, (_, magnitude)| peak.max(*magnitude));
+    if peak <= 0.0 {
+        return Vec::new();
+    }
+
+    magnitudes
+        .into_iter()
+        .map(|(frequency, magnitude)| CurvePoint {
+            frequency: frequency as f32,
+            gain_db: ((20.0 * (magnitude / peak).log10()) as f32).max(MIN_IR_GAIN_DB),
+        })
+        .collect()
+}
@@ -0,0 +1,268 @@
+//! The plugin's GUI, which shows a level meter for every band along with the input and output
+//! peak levels and the output's stereo correlation.
+
+use atomic_float::AtomicF32;
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, Color32, Pos2, Rect, Sense};
+use nih_plug_egui::{create_egui_editor, EguiState};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::filterbank::BandSpacing;
+use crate::{Character, NoiseGenerator, StlVocoderParams, MAX_BANDS};
+
+/// The level at the bottom of the band meters.
+const METER_MIN_DB: f32 = -60.0;
+/// How much of the displayed level remains after every frame once the band's level drops.
+const METER_DECAY: f32 = 0.9;
+/// The seed for the randomize button's random numbers. Every time the editor is opened, the
+/// button goes through the same sequence of settings.
+const DICE_SEED: u32 = 0x5EED;
+
+pub fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(640, 320)
+}
+
+/// The editor's own state. The editor keeps its own copy of the levels so the meters can fall
+/// smoothly.
+struct DisplayedLevels {
+    bands: Vec<f32>,
+    input_peak: f32,
+    output_peak: f32,
+    /// The path entered for loading a band gain curve, and the result of the last attempt.
+    curve_path: String,
+    curve_status: String,
+    /// The random number generator for the randomize button.
+    dice: NoiseGenerator,
+}
+
+pub fn create(
+    params: Arc<StlVocoderParams>,
+    band_levels: Arc<[AtomicF32]>,
+    effective_bands: Arc<AtomicUsize>,
+    input_peak: Arc<AtomicF32>,
+    output_peak: Arc<AtomicF32>,
+    correlation: Arc<AtomicF32>,
+    editor_state: Arc<EguiState>,
+) -> Option<Box<dyn Editor>> {
+    let displayed_levels = DisplayedLevels {
+        bands: vec![0.0; MAX_BANDS],
+        input_peak: 0.0,
+        output_peak: 0.0,
+        curve_path: String::new(),
+        curve_status: String::new(),
+        dice: NoiseGenerator::new(DICE_SEED),
+    };
+
+    create_egui_editor(
+        editor_state,
+        displayed_levels,
+        |_, _| {},
+        move |egui_ctx, setter, displayed_levels| {
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Character:");
+                    for (character_idx, name) in Character::variants().iter().enumerate() {
+                        if ui.button(*name).clicked() {
+                            apply_character(&params, setter, Character::from_index(character_idx));
+                        }
+                    }
+                    if ui
+                        .button("Randomize")
+                        .on_hover_text(
+                            "Randomize the band Q, spacing, sibilance, and formant shift",
+                        )
+                        .clicked()
+                    {
+                        roll_dice(&params, setter, &mut displayed_levels.dice);
+                    }
+                });
+                displayed_levels.input_peak = input_peak
+                    .load(Ordering::Relaxed)
+                    .max(displayed_levels.input_peak * METER_DECAY);
+                displayed_levels.output_peak = output_peak
+                    .load(Ordering::Relaxed)
+                    .max(displayed_levels.output_peak * METER_DECAY);
+                ui.label(format!(
+                    "Input: {:.1} dB    Output: {:.1} dB    Correlation: {:+.2}",
+                    util::gain_to_db(displayed_levels.input_peak).max(METER_MIN_DB),
+                    util::gain_to_db(displayed_levels.output_peak).max(METER_MIN_DB),
+                    correlation.load(Ordering::Relaxed),
+                ));
+
+                ui.horizontal(|ui| {
+                    ui.label("Band gain curve:");
+                    ui.text_edit_singleline(&mut displayed_levels.curve_path);
+                    if ui.button("Load").clicked() {
+                        displayed_levels.curve_status = match params
+                            .load_band_gain_curve(Path::new(displayed_levels.curve_path.trim()))
+                        {
+                            Ok(()) => String::from("Loaded"),
+                            Err(err) => format!("Could not load the curve: {err}"),
+                        };
+                    }
+                    if ui.button("Clear").clicked() {
+                        params.clear_band_gain_curve();
+                        displayed_levels.curve_status.clear();
+                    }
+                    ui.label(&displayed_levels.curve_status);
+                });
+
+                ui.heading("Band Levels");
+
+                // The band count parameter may have been capped, and the meters only show the
+                // bands that are actually there
+                let bands = effective_bands.load(Ordering::Relaxed).clamp(1, MAX_BANDS);
+                let requested_bands = params.bands.value() as usize;
+                if bands < requested_bands {
+                    ui.label(format!("Using {bands} of {requested_bands} bands"));
+                }
+
+                let (rect, _) = ui.allocate_exact_size(ui.available_size(), Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, Color32::from_gray(24));
+
+                let bar_width = rect.width() / bands as f32;
+                for (band_idx, (level, displayed_level)) in band_levels
+                    .iter()
+                    .zip(displayed_levels.bands.iter_mut())
+                    .take(bands)
+                    .enumerate()
+                {
+                    // The bars jump up immediately, but fall off gradually
+                    *displayed_level = level
+                        .load(Ordering::Relaxed)
+                        .max(*displayed_level * METER_DECAY);
+
+                    let level_db = util::gain_to_db(*displayed_level);
+                    let height = ((level_db - METER_MIN_DB) / -METER_MIN_DB).clamp(0.0, 1.0);
+                    let left = rect.left() + band_idx as f32 * bar_width;
+                    painter.rect_filled(
+                        Rect::from_min_max(
+                            Pos2::new(left, rect.bottom() - height * rect.height()),
+                            Pos2::new(left + (bar_width - 1.0).max(1.0), rect.bottom()),
+                        ),
+                        0.0,
+                        Color32::from_rgb(255, 170, 60),
+                    );
+                }
+            });
+
+            // The meters need to keep falling even when nothing else happens
+            egui_ctx.request_repaint();
+        },
+    )
+}
+
+/// Set the parameters `character` configures to its settings.
+fn apply_character(params: &StlVocoderParams, setter: &ParamSetter, character: Character) {
+    let settings = character.settings();
+    set_parameter(setter, &params.band_q, settings.band_q);
+    set_parameter(setter, &params.spacing, settings.spacing);
+    set_parameter(setter, &params.sibilance_amount, settings.sibilance_amount);
+    set_parameter(setter, &params.pre_emphasis, settings.pre_emphasis);
+}
+
+/// The tone parameter values picked by [`roll()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DiceSettings {
+    band_q: f32,
+    spacing: BandSpacing,
+    sibilance_amount: f32,
+    formant_shift: f32,
+}
+
+/// Pick random values for the tone parameters within ranges that still sound musical. The levels
+/// and the dry/wet mix are left alone, so this never makes anything suddenly louder.
+fn roll(dice: &mut NoiseGenerator) -> DiceSettings {
+    // The noise generator produces values in `[-1, 1]`
+    let mut uniform = || (dice.next_sample() + 1.0) * 0.5;
+
+    // The Q is picked on a logarithmic scale, so narrow and wide bands are equally likely
+    let band_q = (0.5f32.ln() + (3.0f32.ln() - 0.5f32.ln()) * uniform()).exp();
+    let spacing = match (uniform() * 3.0) as usize {
+        0 => BandSpacing::Logarithmic,
+        1 => BandSpacing::Mel,
+        _ => BandSpacing::Bark,
+    };
+    let sibilance_amount = 0.1 + 0.7 * uniform();
+    // This matches the formant shift parameter's step size
+    let formant_shift = ((uniform() * 2.0 - 1.0) * 50.0).round() / 10.0;
+
+    DiceSettings {
+        band_q,
+        spacing,
+        sibilance_amount,
+        formant_shift,
+    }
+}
+
+/// Set the tone parameters to the next random values from [`roll()`].
+fn roll_dice(params: &StlVocoderParams, setter: &ParamSetter, dice: &mut NoiseGenerator) {
+    let settings = roll(dice);
+    set_parameter(setter, &params.band_q, settings.band_q);
+    set_parameter(setter, &params.spacing, settings.spacing);
+    set_parameter(setter, &params.sibilance_amount, settings.sibilance_amount);
+    set_parameter(setter, &params.formant_shift, settings.formant_shift);
+}
+
+/// Set a parameter as a single gesture.
+fn set_parameter<P: Param>(setter: &ParamSetter, param: &P, value: P::Plain) {
+    setter.begin_set_parameter(param);
+    setter.set_parameter(param, value);
+    setter.end_set_parameter(param);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dice_seed_gives_a_fixed_sequence() {
+        let expected = [
+            (0.647_693_8, BandSpacing::Bark, 0.288_679_4, -2.6),
+            (1.452_039_6, BandSpacing::Logarithmic, 0.465_209_07, -1.1),
+            (1.397_271_6, BandSpacing::Logarithmic, 0.469_377_64, -1.2),
+        ];
+
+        let mut dice = NoiseGenerator::new(DICE_SEED);
+        for (band_q, spacing, sibilance_amount, formant_shift) in expected {
+            let settings = roll(&mut dice);
+            assert!((settings.band_q - band_q).abs() < 1e-5);
+            assert_eq!(settings.spacing, spacing);
+            assert!((settings.sibilance_amount - sibilance_amount).abs() < 1e-5);
+            assert!((settings.formant_shift - formant_shift).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn dice_only_roll_tone_parameters_within_their_ranges() {
+        let params = StlVocoderParams::default();
+        let is_in_range = |param: &FloatParam, value: f32| {
+            (param.preview_plain(param.preview_normalized(value)) - value).abs() < 1e-4
+        };
+
+        let mut dice = NoiseGenerator::new(DICE_SEED);
+        for _ in 0..1000 {
+            // This lists every field, so the dice can't start setting a gain or the dry/wet mix
+            // without this test being updated
+            let DiceSettings {
+                band_q,
+                spacing: _,
+                sibilance_amount,
+                formant_shift,
+            } = roll(&mut dice);
+
+            assert!(is_in_range(&params.band_q, band_q), "band_q = {band_q}");
+            assert!(
+                is_in_range(&params.sibilance_amount, sibilance_amount),
+                "sibilance_amount = {sibilance_amount}"
+            );
+            assert!(
+                is_in_range(&params.formant_shift, formant_shift),
+                "formant_shift = {formant_shift}"
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! A C ABI for embedding the [`Vocoder`] core in hosts that aren't written in Rust. This is only
+//! built with the `ffi` feature enabled.
+//!
+//! The calling convention is the platform's C calling convention. A vocoder is created with
+//! [`vocoder_create()`], which returns an opaque pointer that's owned by the caller until it's
+//! passed to [`vocoder_destroy()`]. In between, [`vocoder_process()`] vocodes one mono block at a
+//! time. The vocoder only ever reads from and writes to the buffers passed to it during that call,
+//! and it never takes ownership of them.
+//!
+//! A single vocoder must not be used from more than one thread at the same time, but it can be
+//! moved between threads. Processing doesn't allocate or lock, so it's safe to call from a
+//! realtime audio thread. The parameters keep their default values.
+
+use std::ptr;
+
+use crate::{Vocoder, VocoderConfig};
+
+/// Create a mono vocoder for `sample_rate`. `max_block_size` is the largest number of samples
+/// that's processed at once internally. [`vocoder_process()`] accepts longer blocks and splits
+/// them up. Returns a null pointer if the sample rate isn't positive or the block size is zero.
+///
+/// The returned vocoder needs to be freed with [`vocoder_destroy()`].
+#[no_mangle]
+pub extern "C" fn vocoder_create(sample_rate: f32, max_block_size: usize) -> *mut Vocoder {
+    if !(sample_rate > 0.0 && sample_rate.is_finite()) || max_block_size == 0 {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(Vocoder::new(VocoderConfig {
+        sample_rate,
+        max_block_size,
+        offline: false,
+    })))
+}
+
+/// Vocode `num_samples` samples of `modulator` with `carrier`, and write the result to `output`.
+/// All state carries over between calls, so a stream can be processed in blocks of any size.
+/// Nothing happens if any of the pointers is null.
+///
+/// # Safety
+///
+/// `vocoder` needs to be a pointer returned from [`vocoder_create()`] that hasn't been destroyed
+/// yet. `modulator` and `carrier` need to point to `num_samples` readable samples, and `output`
+/// needs to point to `num_samples` writable samples. `output` may be the same pointer as
+/// `modulator` to process in place, but it must not overlap `carrier` or only partially overlap
+/// `modulator`.
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_process(
+    vocoder: *mut Vocoder,
+    modulator: *const f32,
+    carrier: *const f32,
+    output: *mut f32,
+    num_samples: usize,
+) {
+    if vocoder.is_null() || modulator.is_null() || carrier.is_null() || output.is_null() {
+        return;
+    }
+
+    // The modulator is vocoded in place, so it's copied to the output first
+    let vocoder = &mut *vocoder;
+    if !ptr::eq(modulator, output) {
+        ptr::copy_nonoverlapping(modulator, output, num_samples);
+    }
+    let output = std::slice::from_raw_parts_mut(output, num_samples);
+    let carrier = std::slice::from_raw_parts(carrier, num_samples);
+
+    for (output_block, carrier_block) in output
+        .chunks_mut(vocoder.max_block_size)
+        .zip(carrier.chunks(vocoder.max_block_size))
+    {
+        vocoder.process(&mut [output_block], Some(&[carrier_block]), None, || None);
+    }
+}
+
+/// Free a vocoder created with [`vocoder_create()`]. Passing a null pointer does nothing.
+///
+/// # Safety
+///
+/// `vocoder` needs to be null or a pointer returned from [`vocoder_create()`] that hasn't been
+/// destroyed yet. It can't be used anymore afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vocoder_destroy(vocoder: *mut Vocoder) {
+    if !vocoder.is_null() {
+        drop(Box::from_raw(vocoder));
+    }
+}
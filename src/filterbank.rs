@@ -0,0 +1,882 @@
+//! The bandpass filter bank that splits the modulator and the carrier into bands, along with the
+//! filter design code behind it. None of this depends on the plugin's parameters, so it can also
+//! be used on its own.
+
+use nih_plug::prelude::*;
+use std::f64::consts::PI;
+
+/// The maximum number of bands. Per-band state is allocated up front for this many bands.
+pub const MAX_BANDS: usize = 256;
+/// The highest supported filter order. Every order adds another biquad section per band.
+pub const MAX_FILTER_ORDER: usize = 8;
+/// The lowest frequency a band can start at, since logarithmic spacing can't start at 0 Hz.
+pub const MIN_BAND_FREQUENCY: f32 = 20.0;
+/// The highest frequency a band's filter can extend to as a fraction of the sample rate, since the
+/// bilinear transform can't handle frequencies at or above the Nyquist frequency.
+const MAX_EDGE_FRACTION: f32 = 0.49;
+/// Filter and envelope state below this magnitude is flushed to zero. Decaying state would
+/// otherwise eventually end up in the denormal range, which is very slow to compute with on some
+/// CPUs. This is far below anything audible.
+pub(crate) const DENORMAL_THRESHOLD: f32 = 1e-15;
+/// Flush `value` to zero if it's small enough to risk turning into a denormal.
+pub(crate) fn flush_denormal(value: f32) -> f32 {
+    if value.abs() < DENORMAL_THRESHOLD {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// The frequency scale the bands are spaced evenly on.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandSpacing {
+    #[name = "Linear"]
+    Linear,
+    /// Every band spans the same musical interval.
+    #[name = "Logarithmic"]
+    Logarithmic,
+    /// The mel scale, which approximates how far apart listeners perceive pitches to be.
+    #[name = "Mel"]
+    Mel,
+    /// The Bark scale, which follows the ear's critical bands. This tends to give the most
+    /// intelligible speech.
+    #[name = "Bark"]
+    Bark,
+}
+
+impl BandSpacing {
+    /// Convert a frequency in Hz to this scale.
+    pub fn frequency_to_scale(self, frequency: f32) -> f32 {
+        match self {
+            BandSpacing::Linear => frequency,
+            BandSpacing::Logarithmic => frequency.ln(),
+            BandSpacing::Mel => 2595.0 * (1.0 + frequency / 700.0).log10(),
+            // Traunmüller's approximation
+            BandSpacing::Bark => 26.81 * frequency / (1960.0 + frequency) - 0.53,
+        }
+    }
+
+    /// Convert a value on this scale back to a frequency in Hz. This is the inverse of
+    /// [`frequency_to_scale()`][Self::frequency_to_scale()].
+    pub fn scale_to_frequency(self, value: f32) -> f32 {
+        match self {
+            BandSpacing::Linear => value,
+            BandSpacing::Logarithmic => value.exp(),
+            BandSpacing::Mel => 700.0 * (10.0f32.powf(value / 2595.0) - 1.0),
+            BandSpacing::Bark => 1960.0 * (value + 0.53) / (26.28 - value),
+        }
+    }
+}
+
+/// Everything that determines a filter bank's filters, apart from the sample rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandLayout {
+    pub low: f32,
+    pub high: f32,
+    pub bands: usize,
+    pub order: usize,
+    pub spacing: BandSpacing,
+    pub spacing_curve: f32,
+    /// How wide every band's filter is compared to the band itself, on a logarithmic scale. At 1.0
+    /// neighbouring filters meet at the band edges, and larger values make them overlap.
+    pub bandwidth: f32,
+    /// The same as `bandwidth`, but for the filters that shape the carrier. [`FilterBank`] itself
+    /// only uses `bandwidth`, so the carrier's filter bank is built from a copy of the layout with
+    /// this as its bandwidth.
+    pub synthesis_bandwidth: f32,
+}
+
+impl Default for BandLayout {
+    fn default() -> Self {
+        Self {
+            low: 0.0,
+            high: 0.0,
+            bands: 0,
+            order: 0,
+            spacing: BandSpacing::Logarithmic,
+            spacing_curve: 0.0,
+            bandwidth: 0.0,
+            synthesis_bandwidth: 0.0,
+        }
+    }
+}
+
+impl BandLayout {
+    /// `bands` logarithmically spaced `order`-th order bands between `low` and `high` Hz.
+    pub fn new(low: f32, high: f32, bands: usize, order: usize) -> Self {
+        Self {
+            low,
+            high,
+            bands,
+            order,
+            spacing: BandSpacing::Logarithmic,
+            spacing_curve: 1.0,
+            bandwidth: 1.0,
+            synthesis_bandwidth: 1.0,
+        }
+    }
+
+    /// Whether the filter bank needs to be rebuilt to go from `self` to `other`. The cutoff
+    /// frequencies may differ by up to `cutoff_tolerance` as a fraction of `self`'s cutoffs.
+    pub fn differs_from(&self, other: &BandLayout, cutoff_tolerance: f32) -> bool {
+        let cutoff_moved = |from: f32, to: f32| (to / from - 1.0).abs() > cutoff_tolerance;
+
+        cutoff_moved(self.low, other.low)
+            || cutoff_moved(self.high, other.high)
+            || self.bands != other.bands
+            || self.order != other.order
+            || self.spacing != other.spacing
+            || self.spacing_curve != other.spacing_curve
+            || self.bandwidth != other.bandwidth
+            || self.synthesis_bandwidth != other.synthesis_bandwidth
+    }
+
+    /// The highest band count up to `self.bands` for which none of the bands are narrower than
+    /// `min_band_width` Hz, or narrower than `min_relative_width` times their center frequency.
+    /// This is at least one band if the layout has any bands.
+    pub fn max_bands(&self, min_band_width: f32, min_relative_width: f32) -> usize {
+        let wide_enough = |bands: usize| {
+            band_edges(self.low, self.high, bands, self.spacing, self.spacing_curve).all(
+                |(low, high)| {
+                    high - low >= min_band_width.max(min_relative_width * (low * high).sqrt())
+                },
+            )
+        };
+        if wide_enough(self.bands) {
+            return self.bands;
+        }
+
+        // The bands only get narrower as more of them are added, so the cap can be found with a
+        // binary search. `fits` always satisfies the minimum width and `too_many` never does.
+        let (mut fits, mut too_many) = (1, self.bands);
+        while too_many - fits > 1 {
+            let bands = (fits + too_many) / 2;
+            if wide_enough(bands) {
+                fits = bands;
+            } else {
+                too_many = bands;
+            }
+        }
+
+        fits.min(self.bands)
+    }
+}
+
+/// A bank of bandpass filters that split a signal into bands. Building, retuning, and copying the
+/// bank doesn't allocate, so this can be done on the audio thread.
+#[derive(Debug)]
+pub struct FilterBank {
+    filters: Vec<BandpassFilter>,
+    /// The phase alignment allpasses for every band, see
+    /// [`process_sample_phase_aligned()`][Self::process_sample_phase_aligned()].
+    phase_rotators: Vec<PhaseRotator>,
+    /// The `(low, high)` edges of every band in `filters`, in Hz. These are the nominal edges,
+    /// before the filters are widened.
+    band_edges: Vec<(f32, f32)>,
+    order: usize,
+}
+
+impl Default for FilterBank {
+    fn default() -> Self {
+        Self {
+            filters: Vec::with_capacity(MAX_BANDS),
+            phase_rotators: Vec::with_capacity(MAX_BANDS),
+            band_edges: Vec::with_capacity(MAX_BANDS),
+            order: 0,
+        }
+    }
+}
+
+impl Clone for FilterBank {
+    fn clone(&self) -> Self {
+        let mut filter_bank = Self::default();
+        filter_bank.clone_from(self);
+
+        filter_bank
+    }
+
+    /// The derived implementation would allocate new vectors, so this copies into the existing
+    /// ones instead.
+    fn clone_from(&mut self, source: &Self) {
+        self.filters.clone_from(&source.filters);
+        self.phase_rotators.clone_from(&source.phase_rotators);
+        self.band_edges.clone_from(&source.band_edges);
+        self.order = source.order;
+    }
+}
+
+impl FilterBank {
+    /// Build a filter bank with `bands` logarithmically spaced `order`-th order bands between
+    /// `low` and `high` Hz.
+    pub fn new(sample_rate: f32, low: f32, high: f32, bands: usize, order: usize) -> Self {
+        let mut filter_bank = Self::default();
+        filter_bank.rebuild(sample_rate, &BandLayout::new(low, high, bands, order));
+
+        filter_bank
+    }
+
+    /// Replace the bank's filters with filters for `layout`, resetting their state. Bands that
+    /// can't be built at this sample rate are left out, and at most [`MAX_BANDS`] bands are built.
+    pub fn rebuild(&mut self, sample_rate: f32, layout: &BandLayout) {
+        self.filters.clear();
+        self.phase_rotators.clear();
+        self.band_edges.clear();
+        self.order = layout.order;
+        for (low, high) in band_edges(
+            layout.low,
+            layout.high,
+            layout.bands.min(MAX_BANDS),
+            layout.spacing,
+            layout.spacing_curve,
+        ) {
+            // The filters are widened around the band's geometric center
+            let center = (low * high).sqrt();
+            let half_width = (high / low).powf(layout.bandwidth / 2.0);
+            let (filter_low, filter_high) = (
+                center / half_width,
+                (center * half_width).min(sample_rate * MAX_EDGE_FRACTION),
+            );
+
+            if let Some(filter) =
+                BandpassFilter::new(layout.order, sample_rate, filter_low, filter_high)
+            {
+                // The lowest band has no lower neighbour to be aligned with
+                let rotator_order = if self.filters.is_empty() {
+                    0
+                } else {
+                    layout.order
+                };
+
+                self.filters.push(filter);
+                self.phase_rotators
+                    .push(PhaseRotator::new(rotator_order, sample_rate, low));
+                self.band_edges.push((low, high));
+            }
+        }
+    }
+
+    /// Take over `other`'s filters. If both banks have the same number of bands and the same
+    /// order, the filters are only retuned and keep their state so this doesn't click. Otherwise
+    /// the filters are replaced.
+    pub fn copy_from(&mut self, other: &FilterBank) {
+        if self.has_same_structure(other) {
+            for (filter, other_filter) in self.filters.iter_mut().zip(&other.filters) {
+                filter.copy_coefficients(other_filter);
+            }
+            for (rotator, other_rotator) in
+                self.phase_rotators.iter_mut().zip(&other.phase_rotators)
+            {
+                rotator.coefficient = other_rotator.coefficient;
+                rotator.order = other_rotator.order;
+            }
+            self.band_edges.clone_from(&other.band_edges);
+        } else {
+            self.clone_from(other);
+        }
+    }
+
+    /// Whether both banks have the same number of bands with the same order, in which case
+    /// [`copy_from()`][Self::copy_from()] keeps the filters' state.
+    pub fn has_same_structure(&self, other: &FilterBank) -> bool {
+        self.filters.len() == other.filters.len() && self.order == other.order
+    }
+
+    pub fn num_bands(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// The nominal `(low, high)` edges of every band, in Hz. Neighbouring bands share their edges.
+    pub fn band_edges(&self) -> &[(f32, f32)] {
+        &self.band_edges
+    }
+
+    /// Run a single sample through every band's filter, yielding the bands' output samples from
+    /// the lowest to the highest band.
+    pub fn process_sample(&mut self, input: f32) -> impl Iterator<Item = f32> + '_ {
+        self.filters
+            .iter_mut()
+            .map(move |filter| filter.process(input))
+    }
+
+    /// The same as [`process_sample()`][Self::process_sample()], but with the bands' phases
+    /// aligned at the band edges. At the edge two neighbouring `order`-th order bands share, their
+    /// phases are `order * 90` degrees apart, which partially cancels them out when they're summed
+    /// back together. Every band's input is therefore passed through `order` first order allpass
+    /// filters at its lower edge, on top of all of the allpasses of the bands below it. That
+    /// delays every band by exactly the right amount at its lower edge while the allpasses the
+    /// neighbouring bands share cancel out.
+    ///
+    /// The allpasses are chained from one band to the next, so this adds one first order allpass
+    /// per filter section to every band. Those are cheaper than the biquads, but this still makes
+    /// the filter bank about a third more expensive.
+    pub fn process_sample_phase_aligned(&mut self, input: f32) -> impl Iterator<Item = f32> + '_ {
+        let mut rotated = f64::from(input);
+        self.filters
+            .iter_mut()
+            .zip(self.phase_rotators.iter_mut())
+            .map(move |(filter, rotator)| {
+                rotated = rotator.process(rotated);
+                filter.process(rotated as f32)
+            })
+    }
+
+    pub fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+        for rotator in &mut self.phase_rotators {
+            rotator.reset();
+        }
+    }
+}
+
+/// A biquad section in transposed direct form II. The coefficients are normalized so `a0` is 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    /// A second order highpass filter, using the coefficients from the Audio EQ Cookbook.
+    pub(crate) fn highpass(sample_rate: f32, frequency: f32, q: f64) -> Self {
+        let omega = 2.0 * PI * f64::from(frequency) / f64::from(sample_rate);
+        let (sin_omega, cos_omega) = omega.sin_cos();
+        let alpha = sin_omega / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        Self {
+            b0: (1.0 + cos_omega) / 2.0 / a0,
+            b1: -(1.0 + cos_omega) / a0,
+            b2: (1.0 + cos_omega) / 2.0 / a0,
+            a1: -2.0 * cos_omega / a0,
+            a2: (1.0 - alpha) / a0,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.s1;
+        self.s1 = self.b1 * input - self.a1 * output + self.s2;
+        self.s2 = self.b2 * input - self.a2 * output;
+        // The states are only flushed together. Flushing just one of them would keep feeding small
+        // errors back into the other, which keeps the filter from ever settling.
+        if self.s1.abs() < f64::from(DENORMAL_THRESHOLD)
+            && self.s2.abs() < f64::from(DENORMAL_THRESHOLD)
+        {
+            self.s1 = 0.0;
+            self.s2 = 0.0;
+        }
+
+        output
+    }
+
+    /// Take over `other`'s coefficients while keeping this section's state.
+    pub(crate) fn copy_coefficients(&mut self, other: &Biquad) {
+        *self = Biquad {
+            s1: self.s1,
+            s2: self.s2,
+            ..*other
+        };
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+/// A minimal complex number type for the filter design math.
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+
+    fn div(self, other: Self) -> Self {
+        let denominator = other.re * other.re + other.im * other.im;
+        Self::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        )
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+
+    fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    fn sqrt(self) -> Self {
+        let norm = self.norm();
+        Self::new(
+            ((norm + self.re) / 2.0).sqrt(),
+            ((norm - self.re) / 2.0).sqrt().copysign(self.im),
+        )
+    }
+}
+
+/// A causal Butterworth bandpass filter, made up of `order` cascaded biquad sections. The filter
+/// keeps its state between calls to [`process()`][Self::process()], so the input can be fed in
+/// blocks of any size. The sections are stored inline so filters can be built on the audio thread.
+#[derive(Debug, Clone, Copy)]
+struct BandpassFilter {
+    sections: [Biquad; MAX_FILTER_ORDER],
+    order: usize,
+}
+
+impl BandpassFilter {
+    /// Design an `order`-th order Butterworth bandpass filter between `low` and `high` Hz using
+    /// the bilinear transform. The gain at the band's center frequency is normalized to 1.
+    ///
+    /// The bilinear transform maps every analog pole to a digital pole inside of the unit circle
+    /// and the sections are computed in double precision, so the filter stays stable at any order
+    /// and bandwidth. Very narrow bands at high orders will ring for a long time though.
+    ///
+    /// Returns `None` if the band edges are not strictly between 0 Hz and the Nyquist frequency,
+    /// or if the resulting filter is otherwise unusable.
+    fn new(order: usize, sample_rate: f32, low: f32, high: f32) -> Option<Self> {
+        if !(low > 0.0 && low < high && high < sample_rate / 2.0)
+            || order == 0
+            || order > MAX_FILTER_ORDER
+        {
+            return None;
+        }
+
+        let sample_rate = f64::from(sample_rate);
+        // The band edges need to be prewarped to end up in the right place after the bilinear
+        // transform
+        let prewarp =
+            |frequency: f32| 2.0 * sample_rate * (PI * f64::from(frequency) / sample_rate).tan();
+        let (low, high) = (prewarp(low), prewarp(high));
+        let bandwidth = high - low;
+        let center_squared = Complex::new(low * high, 0.0);
+
+        // Every lowpass prototype pole maps to a pair of bandpass poles. Each of those forms a
+        // biquad section together with its complex conjugate, which is why only the prototype
+        // poles in the upper half of the s-plane plus the real pole for odd orders are needed.
+        let mut sections = [Biquad::default(); MAX_FILTER_ORDER];
+        let mut num_sections = 0;
+        let mut add_section = |pole_a: Complex, pole_b: Complex| {
+            sections[num_sections] = Self::bilinear_section(pole_a, pole_b, sample_rate);
+            num_sections += 1;
+        };
+        for k in 0..order / 2 {
+            let theta = PI * (2 * k + 1 + order) as f64 / (2 * order) as f64;
+            let prototype_pole = Complex::new(theta.cos(), theta.sin()).scale(bandwidth);
+            let discriminant = prototype_pole
+                .mul(prototype_pole)
+                .sub(center_squared.scale(4.0))
+                .sqrt();
+            let pole_a = prototype_pole.add(discriminant).scale(0.5);
+            let pole_b = prototype_pole.sub(discriminant).scale(0.5);
+
+            add_section(pole_a, Complex::new(pole_a.re, -pole_a.im));
+            add_section(pole_b, Complex::new(pole_b.re, -pole_b.im));
+        }
+        if order % 2 == 1 {
+            let prototype_pole = Complex::new(-bandwidth, 0.0);
+            let discriminant = prototype_pole
+                .mul(prototype_pole)
+                .sub(center_squared.scale(4.0))
+                .sqrt();
+
+            add_section(
+                prototype_pole.add(discriminant).scale(0.5),
+                prototype_pole.sub(discriminant).scale(0.5),
+            );
+        }
+
+        // Normalize every section to unity gain at the band's (digital) center frequency
+        let center = 2.0 * (center_squared.re.sqrt() / (2.0 * sample_rate)).atan();
+        let z_inv = Complex::new(center.cos(), -center.sin());
+        let z_inv_squared = z_inv.mul(z_inv);
+        for section in &mut sections[..order] {
+            let numerator = Complex::new(1.0, 0.0).sub(z_inv_squared);
+            let denominator = Complex::new(1.0, 0.0)
+                .add(z_inv.scale(section.a1))
+                .add(z_inv_squared.scale(section.a2));
+            let gain = 1.0 / numerator.div(denominator).norm();
+
+            section.b0 = gain;
+            section.b2 = -gain;
+        }
+
+        let is_finite = sections[..order].iter().all(|section| {
+            [section.b0, section.b2, section.a1, section.a2]
+                .iter()
+                .all(|coefficient| coefficient.is_finite())
+        });
+
+        is_finite.then_some(Self { sections, order })
+    }
+
+    /// Map a pair of analog poles (with zeros at DC and at infinity) to a digital biquad section
+    /// with the bilinear transform. The section's gain is normalized afterwards.
+    fn bilinear_section(pole_a: Complex, pole_b: Complex, sample_rate: f64) -> Biquad {
+        let to_z = |pole: Complex| {
+            let pole = pole.scale(1.0 / (2.0 * sample_rate));
+            Complex::new(1.0 + pole.re, pole.im).div(Complex::new(1.0 - pole.re, -pole.im))
+        };
+        let (z_a, z_b) = (to_z(pole_a), to_z(pole_b));
+
+        Biquad {
+            b0: 1.0,
+            b1: 0.0,
+            b2: -1.0,
+            a1: -z_a.add(z_b).re,
+            a2: z_a.mul(z_b).re,
+            ..Default::default()
+        }
+    }
+
+    /// Take over `other`'s coefficients while keeping the filter's state, so the filter can be
+    /// retuned without clicking. Both filters need to have the same order.
+    fn copy_coefficients(&mut self, other: &BandpassFilter) {
+        for (section, other_section) in self.sections[..self.order]
+            .iter_mut()
+            .zip(&other.sections[..other.order])
+        {
+            section.copy_coefficients(other_section);
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        // Every section attenuates a decaying signal further, so the last section's output can end
+        // up far below the state it's computed from
+        flush_denormal(
+            self.sections[..self.order]
+                .iter_mut()
+                .fold(f64::from(input), |sample, section| section.process(sample))
+                as f32,
+        )
+    }
+
+    fn reset(&mut self) {
+        for section in &mut self.sections[..self.order] {
+            section.reset();
+        }
+    }
+}
+
+/// A cascade of identical first order allpass filters whose phase is shifted by 90 degrees per
+/// filter at the break frequency.
+#[derive(Debug, Clone, Copy)]
+struct PhaseRotator {
+    coefficient: f64,
+    order: usize,
+    /// The previous input and output of every section.
+    states: [(f64, f64); MAX_FILTER_ORDER],
+}
+
+impl PhaseRotator {
+    /// An `order`-th order allpass cascade with its break frequency at `frequency` Hz. The
+    /// coefficient is prewarped so the break frequency ends up in the right place.
+    fn new(order: usize, sample_rate: f32, frequency: f32) -> Self {
+        let tan = (PI * f64::from(frequency) / f64::from(sample_rate)).tan();
+
+        Self {
+            coefficient: (tan - 1.0) / (tan + 1.0),
+            order: order.min(MAX_FILTER_ORDER),
+            states: [(0.0, 0.0); MAX_FILTER_ORDER],
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        self.states[..self.order].iter_mut().fold(
+            input,
+            |sample, (previous_input, previous_output)| {
+                let output = self.coefficient * sample + *previous_input
+                    - self.coefficient * *previous_output;
+                *previous_input = sample;
+                *previous_output = if output.abs() < f64::from(DENORMAL_THRESHOLD) {
+                    0.0
+                } else {
+                    output
+                };
+
+                output
+            },
+        )
+    }
+
+    fn reset(&mut self) {
+        self.states = [(0.0, 0.0); MAX_FILTER_ORDER];
+    }
+}
+
+/// Split the range between `low` and `high` into `bands` `(low, high)` band edges that are spaced
+/// evenly on the `spacing` scale. The edges' positions on that scale are raised to the power of
+/// `curve`, so values other than 1.0 make the bands narrower towards one end of the range. This
+/// yields no bands if the range is empty. The edges are computed lazily so this can be used on the
+/// audio thread.
+fn band_edges(
+    low: f32,
+    high: f32,
+    bands: usize,
+    spacing: BandSpacing,
+    curve: f32,
+) -> impl Iterator<Item = (f32, f32)> {
+    let low = low.max(MIN_BAND_FREQUENCY);
+    let bands = if low < high { bands } else { 0 };
+    let (scale_low, scale_high) = (
+        spacing.frequency_to_scale(low),
+        spacing.frequency_to_scale(high),
+    );
+
+    // Neighbouring bands share their edges, and the outermost edges are pinned to the range so the
+    // bands cover all of it without gaps or rounding errors
+    let edge = move |edge_idx: usize| match edge_idx {
+        0 => low,
+        _ if edge_idx == bands => high,
+        _ => spacing.scale_to_frequency(
+            scale_low + (scale_high - scale_low) * (edge_idx as f32 / bands as f32).powf(curve),
+        ),
+    };
+
+    (0..bands).map(move |band_idx| (edge(band_idx), edge(band_idx + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Render an exponential sine sweep from `low` to `high` Hz to a 32-bit float WAV file in
+    /// memory. A sweep like this spends the same amount of time in every octave.
+    fn sweep_wav(sample_rate: u32, seconds: f32, low: f32, high: f32) -> Vec<u8> {
+        let num_samples = (sample_rate as f32 * seconds) as usize;
+        let rate = (high / low).ln() / seconds;
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for idx in 0..num_samples {
+            let time = idx as f32 / sample_rate as f32;
+            let phase = std::f32::consts::TAU * low * ((rate * time).exp() - 1.0) / rate;
+            writer.write_sample(phase.sin() * 0.5).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        wav.into_inner()
+    }
+
+    /// The total energy of every band after running `samples` through `filter_bank`.
+    fn band_energies(filter_bank: &mut FilterBank, samples: &[f32]) -> Vec<f64> {
+        let mut energies = vec![0.0; filter_bank.num_bands()];
+        for sample in samples {
+            for (energy, band_sample) in
+                energies.iter_mut().zip(filter_bank.process_sample(*sample))
+            {
+                *energy += f64::from(band_sample).powi(2);
+            }
+        }
+
+        energies
+    }
+
+    /// The magnitude of `impulse_response`'s spectrum at `frequency`.
+    fn magnitude_at(impulse_response: &[f32], sample_rate: f32, frequency: f32) -> f64 {
+        let omega = 2.0 * PI * f64::from(frequency / sample_rate);
+        let (re, im) =
+            impulse_response
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (idx, sample)| {
+                    let phase = omega * idx as f64;
+                    let sample = f64::from(*sample);
+                    (re + sample * phase.cos(), im - sample * phase.sin())
+                });
+
+        (re * re + im * im).sqrt()
+    }
+
+    #[test]
+    fn impulse_response_has_unity_gain_at_band_centers() {
+        let sample_rate = 48000.0;
+        for order in [1, 2, 4, 8] {
+            let mut filter_bank = FilterBank::new(sample_rate, 100.0, 10000.0, 12, order);
+            let num_bands = filter_bank.num_bands();
+            let mut impulse_responses = vec![Vec::new(); num_bands];
+            for idx in 0..sample_rate as usize {
+                let input = if idx == 0 { 1.0 } else { 0.0 };
+                for (impulse_response, sample) in impulse_responses
+                    .iter_mut()
+                    .zip(filter_bank.process_sample(input))
+                {
+                    impulse_response.push(sample);
+                }
+            }
+
+            for ((low, high), impulse_response) in
+                filter_bank.band_edges().iter().zip(&impulse_responses)
+            {
+                assert!(impulse_response.iter().all(|sample| sample.is_finite()));
+                // The filters are stable, so the response dies out well within a second
+                let tail_peak = impulse_response[impulse_response.len() / 2..]
+                    .iter()
+                    .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+                assert!(tail_peak < 1e-6, "order {order} still rings at {tail_peak}");
+
+                // These are Butterworth bandpasses, so they're at -3 dB at the band edges
+                let center = (low * high).sqrt();
+                let center_gain = magnitude_at(impulse_response, sample_rate, center);
+                assert!(
+                    (center_gain - 1.0).abs() < 1e-3,
+                    "{center_gain} at {center} Hz"
+                );
+                for edge in [*low, *high] {
+                    let edge_gain = magnitude_at(impulse_response, sample_rate, edge);
+                    assert!(
+                        (edge_gain - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-3,
+                        "{edge_gain} at {edge} Hz"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bands_cover_the_whole_range() {
+        for spacing in [
+            BandSpacing::Linear,
+            BandSpacing::Logarithmic,
+            BandSpacing::Mel,
+            BandSpacing::Bark,
+        ] {
+            for bands in [1, 2, 7, 32, MAX_BANDS] {
+                let mut filter_bank = FilterBank::default();
+                filter_bank.rebuild(
+                    44100.0,
+                    &BandLayout {
+                        spacing,
+                        ..BandLayout::new(80.0, 12000.0, bands, 4)
+                    },
+                );
+                assert_eq!(filter_bank.num_bands(), bands);
+
+                let band_edges = filter_bank.band_edges();
+                assert_eq!(band_edges.first().unwrap().0, 80.0);
+                assert_eq!(band_edges.last().unwrap().1, 12000.0);
+                for (low, high) in band_edges {
+                    assert!(low < high, "{spacing:?} has an empty band at {low} Hz");
+                }
+                for window in band_edges.windows(2) {
+                    assert_eq!(window[0].1, window[1].0, "{spacing:?} has a gap");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decaying_state_is_flushed_to_zero() {
+        // Narrow high order bands ring the longest
+        let mut filter_bank = FilterBank::new(44100.0, 40.0, 200.0, 16, MAX_FILTER_ORDER);
+        for idx in 0..44100 * 20 {
+            let input = if idx < 4410 {
+                (idx as f32 * 0.05).sin() * (-(idx as f32) / 500.0).exp()
+            } else {
+                0.0
+            };
+            for sample in filter_bank.process_sample(input) {
+                assert!(!sample.is_subnormal(), "denormal output at sample {idx}");
+            }
+        }
+
+        for filter in &filter_bank.filters {
+            for section in &filter.sections[..filter.order] {
+                assert_eq!((section.s1, section.s2), (0.0, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn mel_and_bark_match_reference_values() {
+        // 1000 Hz is 1000 mel by definition, and it's right in between the 8th and the 9th
+        // critical band
+        assert!((BandSpacing::Mel.frequency_to_scale(1000.0) - 1000.0).abs() < 0.1);
+        assert!((BandSpacing::Bark.frequency_to_scale(1000.0) - 8.527).abs() < 0.01);
+        for spacing in [BandSpacing::Mel, BandSpacing::Bark] {
+            for frequency in [20.0, 440.0, 1000.0, 8000.0, 20000.0] {
+                let round_trip = spacing.scale_to_frequency(spacing.frequency_to_scale(frequency));
+                assert!((round_trip - frequency).abs() < frequency * 1e-4);
+            }
+        }
+
+        // The inner edges of four bands between 100 Hz and 8 kHz, computed separately
+        for (spacing, expected_edges) in [
+            (BandSpacing::Mel, [752.771, 1938.181, 4090.843]),
+            (BandSpacing::Bark, [609.518, 1453.91, 3124.907]),
+        ] {
+            let band_edges: Vec<_> = band_edges(100.0, 8000.0, 4, spacing, 1.0)
+                .map(|(_, high)| high)
+                .collect();
+            for (edge, expected_edge) in band_edges.iter().zip(expected_edges) {
+                assert!(
+                    (edge - expected_edge).abs() < 0.01,
+                    "{spacing:?} has an edge at {edge} Hz instead of {expected_edge} Hz"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sweep_spreads_evenly_over_logarithmic_bands() {
+        let (seconds, sweep_low, sweep_high) = (8.0, 20.0, 20000.0);
+        let mut reader = hound::WavReader::new(Cursor::new(sweep_wav(
+            44100, seconds, sweep_low, sweep_high,
+        )))
+        .unwrap();
+        let sample_rate = reader.spec().sample_rate as f32;
+        let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+
+        let mut filter_bank = FilterBank::new(sample_rate, 100.0, 8000.0, 16, 4);
+        assert_eq!(filter_bank.num_bands(), 16);
+        let energies = band_energies(&mut filter_bank, &samples);
+
+        // A band spanning `n` octaves gets the sweep's energy for as long as the sweep takes to
+        // cover `n` octaves, which is the same for every band
+        for ((low, high), energy) in filter_bank.band_edges().iter().zip(&energies) {
+            let seconds_in_band = seconds * (high / low).ln() / (sweep_high / sweep_low).ln();
+            let expected_energy = 0.5f64.powi(2) / 2.0 * f64::from(seconds_in_band * sample_rate);
+            let deviation_db = 10.0 * (energy / expected_energy).log10();
+            assert!(
+                deviation_db.abs() < 0.25,
+                "The band from {low} to {high} Hz is {deviation_db} dB off"
+            );
+        }
+    }
+}
@@ -1,11 +1,328 @@
-use butterworth::{Cutoff, Filter};
+pub use atomic_float::AtomicF32;
 use core::f32;
+use curve::CurvePoint;
+use filterbank::{
+    flush_denormal, BandLayout, BandSpacing, Biquad, FilterBank, MAX_BANDS, MAX_FILTER_ORDER,
+    MIN_BAND_FREQUENCY,
+};
+use limiter::Limiter;
 use nih_plug::prelude::*;
-use std::sync::Arc;
+use nih_plug_egui::EguiState;
+use oversampling::{Downsampler, Upsampler};
+use serde::{Deserialize, Serialize};
+use spectral::{FftOverlap, FftWindow, SpectralSettings, SpectralVocoder};
+use std::f32::consts::{FRAC_PI_4, SQRT_2, TAU};
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use synth::{BuzzOscillator, CarrierSynth};
+use sysex::BandControlMessage;
 
+mod curve;
+mod editor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filterbank;
+mod limiter;
+mod oversampling;
+mod spectral;
+mod synth;
+mod sysex;
+
+/// The number of band gain trims. These are spread out over the active bands.
+const NUM_BAND_GAINS: usize = 32;
+/// The number of band pan positions. Like the band gain trims, these are spread out over the
+/// active bands.
+const NUM_BAND_PANS: usize = 8;
+/// The highest band edge as a fraction of the sample rate. This keeps the band filters away from
+/// the Nyquist frequency.
+const MAX_FREQUENCY_FRACTION: f32 = 0.49;
+/// Modulator energy above this frequency is considered to be sibilance.
+const SIBILANCE_FREQUENCY: f32 = 4000.0;
+/// The time constant for the sibilance detector's envelope followers.
+const SIBILANCE_DETECTOR_MS: f32 = 5.0;
+/// The pre-emphasis filter's coefficient at 100% pre-emphasis. This is the `a` in
+/// `y[n] = x[n] - a * x[n - 1]`.
+const MAX_PRE_EMPHASIS_COEFFICIENT: f32 = 0.95;
+/// The lowest envelope level the carrier bands are normalized by when the main input vocodes
+/// itself. Quieter bands are attenuated instead of being amplified up to full level.
+const SELF_VOCODING_ENVELOPE_FLOOR: f32 = 1e-4;
+/// How much wider the band filters are with analog emulation enabled, on a logarithmic scale.
+const ANALOG_BAND_OVERLAP: f32 = 1.5;
+/// How hard every band is driven into its saturator with analog emulation enabled.
+const ANALOG_DRIVE: f32 = 2.0;
+/// The gain difference between the lowest band and the center at full tilt, in decibels.
+const TILT_RANGE_DB: f32 = 12.0;
+/// How quickly the spectral match follows changes in the carrier's and the modulator's tilt, and
+/// the largest correction it applies at either end of the band range.
+const SPECTRAL_MATCH_MS: f32 = 500.0;
+const SPECTRAL_MATCH_MAX_DB: f32 = 12.0;
+/// The band count the automatic gain compensation is calibrated for. The output level stays the
+/// same as without compensation at this band count.
+const AUTO_GAIN_REFERENCE_BANDS: f32 = 20.0;
+/// While any band envelope or output sample is above this level (-100 dB) the plugin asks the host
+/// to keep processing, so decaying envelopes and ringing filters aren't cut off.
+const TAIL_THRESHOLD: f32 = 1e-5;
+/// Once both inputs and the output have been below [`TAIL_THRESHOLD`] for this long, the band
+/// processing is skipped until either input picks up again.
+const SILENCE_TIMEOUT_MS: f32 = 500.0;
+/// The highest band count in eco mode.
+const ECO_MAX_BANDS: usize = 16;
+/// The highest filter order in eco mode.
+const ECO_MAX_FILTER_ORDER: usize = 2;
+/// How long the wet signal takes to fade out before and back in after switching eco mode.
+const ECO_FADE_MS: f32 = 10.0;
+/// The dry crossover is disabled at this frequency.
+const DRY_CROSSOVER_OFF_HZ: f32 = 20.0;
+/// The carrier bass bypass is disabled at this frequency.
+const CARRIER_BASS_BYPASS_OFF_HZ: f32 = 20.0;
+/// No band is allowed to be narrower than this, in Hz. Bands this narrow barely let anything
+/// through, so the band count is capped instead of spending CPU time on them. The minimum band
+/// width parameter can raise this further.
+const MIN_BAND_WIDTH_HZ: f32 = 1.0;
+/// How long the old and the new carrier filter bank are crossfaded for after the band layout
+/// changes.
+const FILTER_BANK_CROSSFADE_MS: f32 = 10.0;
+/// The minimum number of 2x oversampling stages when rendering offline.
+const OFFLINE_MIN_OVERSAMPLING_STAGES: usize = 1;
+/// How many filter sections are added to every band when rendering offline.
+const OFFLINE_EXTRA_FILTER_ORDER: usize = 1;
+/// How quickly the ducker opens up again once the modulator comes back in, and how slowly it
+/// closes once the modulator goes quiet.
+const DUCK_ATTACK_MS: f32 = 5.0;
+const DUCK_RELEASE_MS: f32 = 200.0;
+/// The ducker starts pulling the wet signal down once the modulator's level falls below this
+/// level (-40 dB). The wet signal is fully ducked when the modulator is completely silent.
+const DUCK_THRESHOLD: f32 = 0.01;
+/// The attack times of the transient detector's fast and slow envelope followers, and the release
+/// time they share.
+const TRANSIENT_FAST_ATTACK_MS: f32 = 0.5;
+const TRANSIENT_SLOW_ATTACK_MS: f32 = 20.0;
+const TRANSIENT_RELEASE_MS: f32 = 100.0;
+/// The most the carrier's gain is raised by on a transient at 100% transient amount, on top of
+/// unity gain. This is +12 dB.
+const MAX_TRANSIENT_BOOST: f32 = 3.0;
+/// The lowest correlation between the left and right output channels the mono safe option allows.
+const MONO_SAFE_MIN_CORRELATION: f32 = 0.0;
+/// How long the mono safe option takes to widen the output back to its full width.
+const MONO_SAFE_RELEASE_MS: f32 = 500.0;
+/// The long-term RMS level the auto normalization brings the modulator to, and the most it's
+/// allowed to boost or cut the modulator by.
+const NORMALIZE_TARGET_DB: f32 = -18.0;
+const NORMALIZE_MAX_GAIN_DB: f32 = 24.0;
+/// The time constant of the auto normalization's RMS level. Blocks quieter than
+/// [`NORMALIZE_GATE_DB`] don't count towards the level, so pauses don't pump up the gain.
+const NORMALIZE_WINDOW_MS: f32 = 3000.0;
+const NORMALIZE_GATE_DB: f32 = -60.0;
+/// How long it takes to fade between the stereo and the mono output.
+const MONO_OUTPUT_FADE_MS: f32 = 20.0;
+/// The output limiter's ceiling, how far it looks ahead, and how long it takes to recover.
+const LIMITER_CEILING_DB: f32 = -0.3;
+const LIMITER_LOOKAHEAD_MS: f32 = 1.5;
+const LIMITER_RELEASE_MS: f32 = 50.0;
+/// The cutoff frequencies of the DC blockers on the input and on the output. These are well below
+/// anything audible, so they only remove DC offsets and the slowest drifts.
+const INPUT_DC_BLOCKER_HZ: f32 = 5.0;
+const OUTPUT_DC_BLOCKER_HZ: f32 = 10.0;
+/// How long the tempo synced gate takes to open or close, so the gate doesn't click.
+const GATE_SYNC_FADE_MS: f32 = 2.0;
+/// The longest supported lookahead time.
+const MAX_LOOKAHEAD_MS: f32 = 10.0;
+/// How long it takes for the envelope followers to come to a halt when freezing, and to get back up
+/// to speed when unfreezing.
+const FREEZE_FADE_MS: f32 = 50.0;
+/// A band's gate only opens again once its envelope rises this far above the gate threshold, so
+/// the gate doesn't chatter around the threshold.
+const GATE_HYSTERESIS_DB: f32 = 3.0;
+/// At or below this threshold the band gates are disabled.
+const GATE_THRESHOLD_OFF_DB: f32 = -90.0;
+/// How far the smoothed cutoff frequencies need to move, relative to the cutoffs the filter bank
+/// was built for, before the band filters are recomputed. This is a little under 9 cents.
+const CUTOFF_REBUILD_THRESHOLD: f32 = 0.005;
+
+/// The plugin itself. This only adapts the host's buffers, events, and transport information to
+/// the [`Vocoder`] core.
 struct StarlightVocoder {
+    vocoder: Vocoder,
+    /// The latency last reported to the host, in samples.
+    latency_samples: u32,
+}
+
+/// The host's playback position while its transport is playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SongPosition {
+    /// The position in quarter notes.
+    beats: f64,
+    /// The tempo in quarter notes per minute.
+    tempo: f64,
+}
+
+/// The settings a [`Vocoder`] is created with. In the plugin these come from the host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VocoderConfig {
+    pub sample_rate: f32,
+    /// The largest number of samples processed at once. [`Vocoder::process_block()`] splits
+    /// longer inputs into blocks of this size.
+    pub max_block_size: usize,
+    /// Use the higher quality settings meant for offline rendering.
+    pub offline: bool,
+}
+
+impl Default for VocoderConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            max_block_size: 512,
+            offline: false,
+        }
+    }
+}
+
+/// The vocoder's DSP, independent of the plugin framework. This can be used on its own to vocode
+/// audio without a plugin host, in which case the parameters keep their default values.
+pub struct Vocoder {
     params: Arc<StlVocoderParams>,
-    // buffer_config: BufferConfig,
+    /// The current envelope level of every band, shown in the editor and available to other code
+    /// through [`Vocoder::band_levels()`]. Bands past the current band count are set to zero.
+    band_levels: Arc<[AtomicF32]>,
+    /// The number of bands the filter bank was built with. This can be lower than the band count
+    /// parameter, for instance when the bands would otherwise be too narrow.
+    effective_bands: Arc<AtomicUsize>,
+    /// The main input's peak level during the last block, before any processing.
+    input_peak: Arc<AtomicF32>,
+    /// The output's peak level during the last block, after the output gain and saturation.
+    output_peak: Arc<AtomicF32>,
+    /// The correlation between the first two output channels during the last block, from -1 for
+    /// opposite polarity to 1 for identical channels. This is 1 for mono outputs and silence.
+    correlation: Arc<AtomicF32>,
+
+    /// The current sample rate, captured in `initialize()`. All filter cutoffs are relative to
+    /// this.
+    sample_rate: f32,
+    /// The filter, envelope, and resampling state for every main output channel.
+    channels: Vec<ChannelState>,
+    /// The band layout the filters were built for.
+    filter_bank_layout: BandLayout,
+    /// The number of 2x oversampling stages the filters were built for.
+    filter_bank_oversampling_stages: usize,
+    /// The minimum relative band width the band count was capped for.
+    filter_bank_min_band_width: f32,
+    /// The filter bank before it's copied to the channels, kept around so the filter bank can be
+    /// rebuilt without allocating.
+    prototype_bank: FilterBank,
+    /// The same bands as `prototype_bank`, but with the synthesis Q instead of the analysis Q.
+    /// This is copied to the channels' carrier filter banks.
+    carrier_prototype_bank: FilterBank,
+    /// The band gain trims' smoothed values for the current block.
+    band_gain_trims: [f32; NUM_BAND_GAINS],
+    /// The band pan positions' smoothed values for the current block.
+    band_pans: [f32; NUM_BAND_PANS],
+    /// The stereo spread's smoothed value for the current block.
+    stereo_spread: f32,
+    /// How much of the output's side signal the mono safe option lets through at the end of the
+    /// last block, between 0 and 1.
+    mono_safe_width: f32,
+    /// How much the output is summed to mono, between 0 and 1. This fades towards the mono output
+    /// parameter's value so toggling it doesn't click.
+    mono_output_amount: f32,
+    /// The output gain for every band at the end of the current block, interpolated from
+    /// `band_gain_trims` and panned according to the band pans and the stereo spread. This is
+    /// indexed by `[side][band_idx]`, where even channels are on the left side and odd channels
+    /// are on the right side. Both sides are allocated for [`MAX_BANDS`] bands.
+    band_gain_targets: [Vec<f32>; 2],
+    /// The band gains at the current sample. These ramp towards `band_gain_targets` over the
+    /// course of every block, so the gains don't jump at block boundaries.
+    band_gains: [Vec<f32>; 2],
+    /// How much `band_gains` changes every sample during the current block.
+    band_gain_steps: [Vec<f32>; 2],
+    /// Every band's envelope attack and release rates for the current block, with the envelope
+    /// tilt applied. These are allocated for [`MAX_BANDS`] bands.
+    band_attack_rates: Vec<f32>,
+    band_release_rates: Vec<f32>,
+    /// For every carrier band, the index of the modulator band whose envelope shapes it. This
+    /// implements the formant shift.
+    formant_map: Vec<usize>,
+    /// The formant shift in semitones `formant_map` was computed for.
+    formant_map_shift: f32,
+    /// The formant shift LFO's phase in `[0, 1)`.
+    lfo_phase: f32,
+    /// The formant shift LFO's output for the current block, in `[-1, 1]`.
+    lfo_value: f32,
+    /// The cutoff frequency the channels' carrier highpass filters were built for.
+    carrier_highpass_frequency: f32,
+    /// The cutoff frequency the channels' air band highpass filters were built for.
+    air_frequency: f32,
+    /// How frozen the envelopes are, between 0 and 1. This fades towards the freeze parameter's
+    /// value so freezing doesn't click.
+    freeze_amount: f32,
+    /// The modulator's long-term mean square for the auto normalization, and the normalization
+    /// gain at the end of the last block.
+    normalize_mean_square: f32,
+    normalize_gain: f32,
+    /// How much brighter the carrier is than the modulator in dB, measured as the balance between
+    /// the energy above and below the center of the band range and smoothed over
+    /// [`SPECTRAL_MATCH_MS`].
+    carrier_tilt_difference_db: f32,
+    /// Whether the filter bank is currently built for eco mode. This lags behind the parameter
+    /// while the wet signal fades out.
+    eco_active: bool,
+    /// The gain the wet signal is faded with while switching eco mode, between 0 and 1.
+    eco_switch_gain: f32,
+    /// Extra gains for every band set over SysEx, see the [`sysex`] module. These are applied on
+    /// top of the band gain parameters and are allocated for [`MAX_BANDS`] bands.
+    sysex_band_gains: Vec<f32>,
+    /// Every band's gain from the band gain curve in the persisted settings. These are kept while
+    /// the settings are locked, and they're allocated for [`MAX_BANDS`] bands.
+    band_curve_gains: Vec<f32>,
+    /// Overrides the freeze parameter when set over SysEx.
+    sysex_freeze: Option<bool>,
+    /// The tempo synced gate's current gain, between 0 and 1.
+    gate_sync_gain: f32,
+    /// The engines the last block was vocoded with.
+    engines: ActiveEngines,
+    /// The number of samples both inputs and the output have been silent for.
+    silent_samples: usize,
+
+    // Scratch buffers for the per-channel processing, sized to the maximum buffer size in
+    // `initialize()` so they don't need to be reallocated in `process()`
+    mono_modulator_scratch: Vec<f32>,
+    modulator_scratch: Vec<f32>,
+    carrier_scratch: Vec<f32>,
+    output_scratch: Vec<f32>,
+    dry_wet_scratch: Vec<f32>,
+    engine_blend_scratch: Vec<f32>,
+    gate_sync_scratch: Vec<f32>,
+    output_gain_scratch: Vec<f32>,
+    carrier_gain_scratch: Vec<f32>,
+    /// The gain the modulator is analyzed with, combining the input trim and the auto
+    /// normalization.
+    modulator_gain_scratch: Vec<f32>,
+    saturation_scratch: Vec<f32>,
+
+    /// The brickwall limiter at the very end of the signal chain.
+    limiter: Limiter,
+    /// Whether the limiter was enabled during the last block.
+    limiter_active: bool,
+
+    /// Whether the host is rendering in real time or offline, captured in `initialize()`. Offline
+    /// rendering uses more oversampling and steeper filters, and ignores eco mode.
+    process_mode: ProcessMode,
+    /// The largest block size the buffers are allocated for.
+    max_block_size: usize,
+
+    /// The internal MIDI carrier synth, used when the carrier source is set to
+    /// [`CarrierSource::InternalSynth`].
+    synth: CarrierSynth,
+    /// The fixed pitch carrier used when the carrier source is set to [`CarrierSource::Robot`].
+    robot_oscillator: BuzzOscillator,
+    /// The synth's or the robot oscillator's output for the current block.
+    synth_scratch: Vec<f32>,
+    /// A copy of the main input for every channel, used as the carrier when the carrier source is
+    /// set to [`CarrierSource::MainInput`] or when the main input is routed to the carrier. These
+    /// hold exactly one block's samples.
+    main_input_scratch: Vec<Vec<f32>>,
 }
 
 /// The [`Params`] derive macro gathers all of the information needed for the wrapper to know about
@@ -14,49 +331,3583 @@ struct StarlightVocoder {
 /// of a parameters struct for multiple identical oscillators/filters/envelopes.
 #[derive(Params)]
 struct StlVocoderParams {
+    /// The editor state, saved together with the parameter state so the custom scaling can be
+    /// restored.
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
+    /// Settings that aren't exposed as automatable parameters, but that are still stored with the
+    /// plugin's state.
+    #[persist = "settings"]
+    pub settings: RwLock<PersistedSettings>,
+
     #[id = "low_freq_cutoff"]
     pub low_freq_cutoff: FloatParam,
     #[id = "high_freq_cutoff"]
     pub high_freq_cutoff: FloatParam,
+    /// The requested number of bands. This is capped so no band gets narrower than
+    /// [`MIN_BAND_WIDTH_HZ`]. The cap is only logged when the plugin is initialized, so a cap that
+    /// kicks in while the cutoffs or the band count are automated only shows up in the editor.
     #[id = "bands"]
     pub bands: IntParam,
+    #[id = "spacing"]
+    pub spacing: EnumParam<BandSpacing>,
+    #[id = "engine"]
+    pub engine: EnumParam<Engine>,
+    /// Mixes the FFT engine's output into the filter bank engine's output, from just the filter
+    /// bank at 0% to just the FFT engine at 100%. Both engines run while this is above 0%, which
+    /// roughly doubles the CPU usage, and the filter bank's output is delayed to line up with the
+    /// FFT engine. This is ignored with the FFT engine.
+    #[id = "engine_blend"]
+    pub engine_blend: FloatParam,
+    /// The FFT engine's window function.
+    #[id = "fft_window"]
+    pub fft_window: EnumParam<FftWindow>,
+    /// How many frames overlap in the FFT engine. Higher overlap factors follow the modulator more
+    /// smoothly but need more CPU time. The latency is [`spectral::LATENCY_SAMPLES`] for all of
+    /// them.
+    #[id = "fft_overlap"]
+    pub fft_overlap: EnumParam<FftOverlap>,
+    #[id = "dry_wet"]
+    pub dry_wet: FloatParam,
+    /// Passes the dry signal below this frequency and the vocoded signal above it, which keeps the
+    /// low end of the input intact.
+    #[id = "dry_crossover"]
+    pub dry_crossover: FloatParam,
+    #[id = "output_gain"]
+    pub output_gain: FloatParam,
+    /// Drives the carrier into the bands harder or softer. Unlike the output gain this is applied
+    /// before the band processing, so it affects the saturation and the analog emulation.
+    #[id = "carrier_gain"]
+    pub carrier_gain: FloatParam,
+    /// Scales the modulator before it's analyzed, so the envelopes and the gate see a consistent
+    /// level. The dry signal and the carrier are left alone.
+    #[id = "input_trim"]
+    pub input_trim: FloatParam,
+    /// Follows the modulator's long-term RMS level and adjusts the analysis level on top of the
+    /// input trim to keep it around [`NORMALIZE_TARGET_DB`].
+    #[id = "input_normalize"]
+    pub input_normalize: BoolParam,
+    /// Soft clips the output after the output gain. At 0% the output is left untouched.
+    #[id = "saturation"]
+    pub saturation: FloatParam,
+    /// Limits the output to [`LIMITER_CEILING_DB`] as the very last step, after the saturation.
+    /// This looks ahead by [`LIMITER_LOOKAHEAD_MS`], which adds that much latency while it's
+    /// enabled.
+    #[id = "limiter"]
+    pub limiter: BoolParam,
+    #[id = "attack"]
+    pub attack_ms: FloatParam,
+    #[id = "release"]
+    pub release_ms: FloatParam,
+    #[id = "envelope_mode"]
+    pub envelope_mode: EnumParam<EnvelopeMode>,
+    #[id = "env_shape"]
+    pub envelope_shape: EnumParam<EnvelopeShape>,
+    /// Shortens the attack and release times for the bands above the middle of the band range and
+    /// lengthens them for the bands below it, so consonants are tracked more closely while the low
+    /// formants stay smooth. At 100% the times are halved for every octave above the middle, and
+    /// at 0% all bands use the same times.
+    #[id = "env_tilt"]
+    pub env_tilt: FloatParam,
+    /// Holds every band's envelope at its last peak for this long after it starts falling. This
+    /// only affects the filter bank engine.
+    #[id = "band_hold"]
+    pub band_hold_ms: FloatParam,
+    /// How long a band takes to fade out by 60 dB from its held peak, independent of the release
+    /// time. Longer times leave reverb-like tails that outlast the modulator. At 0 ms the band
+    /// simply follows its envelope after the hold time.
+    #[id = "band_decay"]
+    pub band_decay_ms: FloatParam,
+    /// Delays the carrier so the envelopes can react before a transient reaches the output.
+    #[id = "lookahead"]
+    pub lookahead_ms: FloatParam,
+    #[id = "carrier_source"]
+    pub carrier_source: EnumParam<CarrierSource>,
+    /// Which of the main and the sidechain input is the modulator when the carrier comes from the
+    /// sidechain. This has no effect with the other carrier sources, which always use the main
+    /// input as the modulator.
+    #[id = "input_routing"]
+    pub input_routing: EnumParam<InputRouting>,
+    /// How long the internal synth's voices take to fade in after a note on.
+    #[id = "synth_attack"]
+    pub synth_attack_ms: FloatParam,
+    /// How long the internal synth's voices take to fade out after a note off. The fading voices
+    /// are still shaped by the modulator.
+    #[id = "synth_release"]
+    pub synth_release_ms: FloatParam,
+    /// The number of notes the internal synth can play at the same time. Once that many voices are
+    /// playing, new notes take over the quietest released voice or else the oldest voice.
+    #[id = "max_voices"]
+    pub max_voices: IntParam,
+    /// The pitch of the robot carrier.
+    #[id = "robot_freq"]
+    pub robot_freq: FloatParam,
+    /// Highpasses the carrier before it's split into bands. Unlike the low cutoff, this doesn't
+    /// change the band layout.
+    #[id = "carrier_hp"]
+    pub carrier_hp: FloatParam,
+    /// Passes the carrier below this frequency straight to the output instead of vocoding it,
+    /// which keeps bass synths punchy. This is split off before the carrier highpass.
+    #[id = "carrier_bass_bypass"]
+    pub carrier_bass_bypass: FloatParam,
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+    #[id = "sibilance_amount"]
+    pub sibilance_amount: FloatParam,
+    /// Briefly boosts the carrier whenever the modulator's level jumps up, which brings back the
+    /// punch of plosives that the envelope smoothing takes away.
+    #[id = "transient_amount"]
+    pub transient_amount: FloatParam,
+    /// Adds an extra band above the band range, so the modulator's highest frequencies aren't
+    /// lost. This passes the carrier and the sibilance noise above the top band, shaped by the
+    /// modulator's level in that range.
+    #[id = "air_amount"]
+    pub air_amount: FloatParam,
+    /// Tilts the modulator's spectrum upwards before it's analyzed, which makes consonants
+    /// clearer. The carrier is not affected.
+    #[id = "pre_emphasis"]
+    pub pre_emphasis: FloatParam,
+    #[id = "filter_order"]
+    pub filter_order: IntParam,
+    /// Compensates for the output level changing with the band count. The narrower the bands get,
+    /// the less of the carrier and the modulator every band lets through.
+    #[id = "auto_gain"]
+    pub auto_gain: BoolParam,
+    /// The narrowest a band is allowed to be, relative to its center frequency. If the bands would
+    /// end up narrower than this the band count is lowered instead, since very narrow bands ring
+    /// and resonate.
+    #[id = "min_band_width"]
+    pub min_band_width: FloatParam,
+    /// Caps the band count and the filter order to save CPU. The band count and filter order
+    /// parameters keep their values and are used again once this is turned off.
+    #[id = "eco_mode"]
+    pub eco_mode: BoolParam,
+    /// Overlaps neighbouring bands and gently saturates every band, which gives the rounder sound
+    /// of classic analog vocoders. The per-band saturation makes this noticeably more expensive
+    /// with high band counts.
+    #[id = "analog_emulation"]
+    pub analog_emulation: BoolParam,
+    /// Scales how narrow every band's filter is relative to its center frequency. Higher values
+    /// give a more resonant, vowel-heavy sound, lower values make neighbouring bands overlap for
+    /// a fuller sound.
+    #[id = "band_q"]
+    pub band_q: FloatParam,
+    /// Scales the band Q for the modulator's filters only. Narrower analysis filters measure the
+    /// modulator's spectrum more precisely.
+    #[id = "analysis_q"]
+    pub analysis_q: FloatParam,
+    /// Scales the band Q for the carrier's filters only. Wider synthesis filters shape the carrier
+    /// more broadly.
+    #[id = "synthesis_q"]
+    pub synthesis_q: FloatParam,
+    /// Aligns the carrier bands' phases where neighbouring bands meet, so they don't partially
+    /// cancel each other out when they're summed. This makes the carrier's filter bank about a
+    /// third more expensive.
+    #[id = "phase_align"]
+    pub phase_align: BoolParam,
+    #[id = "formant_shift"]
+    pub formant_shift: FloatParam,
+    /// The rate of the sine LFO that modulates the formant shift.
+    #[id = "lfo_rate"]
+    pub lfo_rate: FloatParam,
+    /// How far the LFO moves the formant shift in either direction, in semitones.
+    #[id = "lfo_depth"]
+    pub lfo_depth: FloatParam,
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<Oversampling>,
+    #[id = "modulator_mono"]
+    pub modulator_mono: BoolParam,
+    /// Shapes every channel's band with the loudest channel's envelope for that band, so the
+    /// stereo image doesn't wobble. Unlike the mono modulator option every channel is still
+    /// analyzed separately. This has no effect while the modulator is summed to mono.
+    #[id = "env_link"]
+    pub env_link: BoolParam,
+    /// Raises the high bands while lowering the low bands for positive values, and the other way
+    /// around for negative values.
+    #[id = "tilt"]
+    pub tilt: FloatParam,
+    /// Tilts the bands to make up for the difference between the carrier's and the modulator's
+    /// overall spectral tilt, so a bright carrier doesn't make a dull voice sound brighter than it
+    /// is. This is applied on top of the tilt, and at 0% it's disabled.
+    #[id = "spectral_match"]
+    pub spectral_match: FloatParam,
+    /// Pans alternating carrier bands to the left and the right to widen the output.
+    #[id = "stereo_spread"]
+    pub stereo_spread: FloatParam,
+    /// Vocodes the carrier's mid and side signals on separate channels instead of its left and
+    /// right channels. The modulator's envelopes shape both.
+    #[id = "ms_mode"]
+    pub ms_mode: BoolParam,
+    /// Narrows the output's stereo image whenever its left and right channels would otherwise
+    /// become less correlated than [`MONO_SAFE_MIN_CORRELATION`], so the output still holds up
+    /// when it's summed to mono.
+    #[id = "mono_safe"]
+    pub mono_safe: BoolParam,
+    /// Sums the output's left and right channels to mono and sends that to both channels. The
+    /// limiter still runs after this.
+    #[id = "mono_output"]
+    pub mono_output: BoolParam,
+    /// Holds the current band envelopes so the carrier keeps the modulator's spectral shape.
+    #[id = "freeze"]
+    pub freeze: BoolParam,
+    /// Rhythmically gates the wet signal in time with the host's tempo.
+    #[id = "gate_sync"]
+    pub gate_sync: EnumParam<GateSync>,
+    /// Blurs every band's level with its neighbours' levels for a smoother, less steppy sound.
+    #[id = "spectral_smoothing"]
+    pub spectral_smoothing: FloatParam,
+    /// Bands whose envelope falls below this level are silenced.
+    #[id = "gate_threshold"]
+    pub gate_threshold: FloatParam,
+    /// Only outputs a single band so it can be auditioned in isolation. -1 disables this, and so
+    /// does a band that's past the current band count.
+    #[id = "solo_band"]
+    pub solo_band: IntParam,
+    /// How far the wet signal is turned down while the modulator is silent. Unlike the band gates
+    /// this follows the modulator's overall level, so the output fades out smoothly between
+    /// phrases.
+    #[id = "duck_depth"]
+    pub duck_depth: FloatParam,
+
+    /// Output trims for groups of neighbouring bands. These are spread out evenly over however
+    /// many bands there are.
+    #[nested(array, group = "Band Gains")]
+    pub band_gains: [BandGainParams; NUM_BAND_GAINS],
+    /// Stereo positions for groups of neighbouring bands, spread out over the bands the same way
+    /// as the band gains. The stereo spread is added on top of these.
+    #[nested(array, group = "Band Pans")]
+    pub band_pans: [BandPanParams; NUM_BAND_PANS],
+}
+
+/// A single band gain trim.
+#[derive(Params)]
+struct BandGainParams {
+    #[id = "band_gain"]
+    pub gain: FloatParam,
+}
+
+impl BandGainParams {
+    fn new(trim_idx: usize) -> Self {
+        Self {
+            gain: FloatParam::new(
+                format!("Band Gain {}", trim_idx + 1),
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-24.0),
+                    max: util::db_to_gain(12.0),
+                    factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+        }
+    }
+}
+
+/// A single band pan position.
+#[derive(Params)]
+struct BandPanParams {
+    #[id = "band_pan"]
+    pub pan: FloatParam,
+}
+
+impl BandPanParams {
+    fn new(pan_idx: usize) -> Self {
+        Self {
+            pan: FloatParam::new(
+                format!("Band Pan {}", pan_idx + 1),
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_value_to_string(formatters::v2s_f32_panning())
+            .with_string_to_value(formatters::s2v_f32_panning()),
+        }
+    }
+}
+
+/// Non-automatable settings that are saved and restored together with the parameters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct PersistedSettings {
+    /// A user defined name for the current settings.
+    pub label: String,
+    /// Warps the band spacing. At 1.0 the bands are spaced evenly on a logarithmic scale, higher
+    /// values pack more bands into the low end, and lower values pack more bands into the high
+    /// end. This is clamped to `[0.25, 4.0]` when used.
+    pub band_spacing_curve: f32,
+    /// A custom gain curve for the bands, loaded from a file with
+    /// [`StlVocoderParams::load_band_gain_curve()`]. See the [`curve`] module for how it's applied.
+    /// This is applied on top of the band gain parameters, and it's empty when no curve is loaded.
+    pub band_gain_curve: Vec<CurvePoint>,
+}
+
+impl Default for PersistedSettings {
+    fn default() -> Self {
+        Self {
+            label: String::new(),
+            band_spacing_curve: 1.0,
+            band_gain_curve: Vec::new(),
+        }
+    }
+}
+
+impl StlVocoderParams {
+    /// Load a band gain curve from the file at `path` and store it in the persisted settings. The
+    /// previous curve is kept if the file can't be loaded.
+    fn load_band_gain_curve(&self, path: &Path) -> io::Result<()> {
+        let band_gain_curve = curve::load(path)?;
+        if let Ok(mut settings) = self.settings.write() {
+            settings.band_gain_curve = band_gain_curve;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the band gain curve, if one is loaded.
+    fn clear_band_gain_curve(&self) {
+        if let Ok(mut settings) = self.settings.write() {
+            settings.band_gain_curve.clear();
+        }
+    }
+}
+
+/// A canned combination of the band Q, band spacing, sibilance, and pre-emphasis settings. This
+/// isn't a parameter. The editor applies a character's settings once when it's selected, after
+/// which they can still be edited individually.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Character {
+    /// The parameters' default values.
+    #[name = "Neutral"]
+    Neutral,
+    /// Resonant, vowel-heavy bands, like a talkbox.
+    #[name = "Talkbox"]
+    Talkbox,
+    /// Narrow, evenly spaced bands without any added noise for a metallic sound.
+    #[name = "Robot"]
+    Robot,
+    /// Wide bands with a lot of noise and emphasized consonants.
+    #[name = "Whisper"]
+    Whisper,
+}
+
+/// The parameter values a [`Character`] sets.
+#[derive(Debug, Clone, Copy)]
+struct CharacterSettings {
+    band_q: f32,
+    spacing: BandSpacing,
+    sibilance_amount: f32,
+    pre_emphasis: f32,
+}
+
+impl Character {
+    fn settings(self) -> CharacterSettings {
+        match self {
+            Character::Neutral => CharacterSettings {
+                band_q: 1.0,
+                spacing: BandSpacing::Logarithmic,
+                sibilance_amount: 0.3,
+                pre_emphasis: 0.3,
+            },
+            Character::Talkbox => CharacterSettings {
+                band_q: 2.0,
+                spacing: BandSpacing::Bark,
+                sibilance_amount: 0.2,
+                pre_emphasis: 0.6,
+            },
+            Character::Robot => CharacterSettings {
+                band_q: 3.0,
+                spacing: BandSpacing::Linear,
+                sibilance_amount: 0.0,
+                pre_emphasis: 0.2,
+            },
+            Character::Whisper => CharacterSettings {
+                band_q: 0.5,
+                spacing: BandSpacing::Bark,
+                sibilance_amount: 1.0,
+                pre_emphasis: 0.8,
+            },
+        }
+    }
+}
+
+/// How the modulator's spectrum is measured and imposed on the carrier.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    /// Bandpass filters and envelope followers, like a classic analog vocoder.
+    #[name = "Filter Bank"]
+    FilterBank,
+    /// Short-time Fourier transforms of the modulator and the carrier, split into the same bands
+    /// as the filter bank. The band edges are much sharper, which sounds smoother at high band
+    /// counts, but this adds [`spectral::LATENCY_SAMPLES`] samples of latency. The envelope mode
+    /// and shape, the band gate, spectral smoothing, analog emulation, phase alignment, and
+    /// oversampling only apply to the filter bank, and so does the envelope tilt.
+    #[name = "FFT"]
+    Fft,
+}
+
+/// Which engines are needed for the current engine and engine blend settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ActiveEngines {
+    filter_bank: bool,
+    fft: bool,
+}
+
+/// How the modulator bands' levels are measured before the attack and release smoothing.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeMode {
+    /// The rectified band signal. This reacts quickly to transients.
+    #[name = "Peak"]
+    Peak,
+    /// The band's RMS level over a window as long as the release time, which gives smoother
+    /// envelopes.
+    #[name = "RMS"]
+    Rms,
+}
+
+/// The shape of the envelope followers' attack and release slopes.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeShape {
+    /// The classic one-pole smoothing, which slows down as it approaches the target level.
+    #[name = "Exponential"]
+    Exponential,
+    /// Moves towards the target level at a constant rate, covering the full 0 to 1 range in the
+    /// attack or release time. This pumps more aggressively.
+    #[name = "Linear"]
+    Linear,
+}
+
+/// Where the carrier signal that gets shaped by the modulator comes from.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum CarrierSource {
+    #[name = "Sidechain"]
+    Sidechain,
+    /// The internal polyphonic synth, played over MIDI.
+    #[name = "Internal Synth"]
+    InternalSynth,
+    /// The main input vocodes itself, which turns the vocoder into a multiband spectral gate.
+    #[name = "Self"]
+    MainInput,
+    /// A buzzy impulse train at the robot frequency, which ignores the carrier's pitch entirely.
+    #[name = "Robot"]
+    Robot,
+}
+
+/// How the main and the sidechain input are assigned to the modulator and the carrier.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum InputRouting {
+    #[name = "Main Is Modulator"]
+    MainIsModulator,
+    /// The main input is the carrier and the sidechain input is the modulator. The modulator is
+    /// still the dry signal.
+    #[name = "Main Is Carrier"]
+    MainIsCarrier,
+}
+
+/// The note division the tempo synced gate opens and closes at.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum GateSync {
+    #[name = "Off"]
+    Off,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/8T"]
+    EighthTriplet,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/16T"]
+    SixteenthTriplet,
+    #[name = "1/32"]
+    ThirtySecond,
+}
+
+impl GateSync {
+    /// The division's length in quarter notes, or `None` when the gate is off.
+    fn beats(self) -> Option<f64> {
+        match self {
+            GateSync::Off => None,
+            GateSync::Quarter => Some(1.0),
+            GateSync::Eighth => Some(0.5),
+            GateSync::EighthTriplet => Some(1.0 / 3.0),
+            GateSync::Sixteenth => Some(0.25),
+            GateSync::SixteenthTriplet => Some(1.0 / 6.0),
+            GateSync::ThirtySecond => Some(0.125),
+        }
+    }
+}
+
+/// How much the band processing is oversampled. Multiplying the carrier bands with the modulator
+/// envelopes creates new high frequency content that would otherwise alias back down.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Oversampling {
+    #[name = "1x"]
+    X1,
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+}
+
+impl Oversampling {
+    /// The number of 2x resampling stages needed for this oversampling factor.
+    fn stages(self) -> usize {
+        match self {
+            Oversampling::X1 => 0,
+            Oversampling::X2 => 1,
+            Oversampling::X4 => 2,
+        }
+    }
+}
+
+/// The parameters for the envelope followers, at the oversampled sample rate.
+#[derive(Debug, Clone, Copy)]
+struct EnvelopeSettings<'a> {
+    shape: EnvelopeShape,
+    /// Every band's attack and release rates. These are one-pole coefficients for exponential
+    /// envelopes, and the maximum change per sample for linear envelopes.
+    attack_rates: &'a [f32],
+    release_rates: &'a [f32],
+    /// The factor all rates are multiplied by, which drops to 0 while freezing.
+    rate_scale: f32,
+    /// The number of samples the band tails hold their peaks for, and the fraction of their level
+    /// they lose every sample after that.
+    hold_samples: u32,
+    decay_rate: f32,
+    /// The one-pole coefficient for the running mean square in the RMS envelope mode, or `None`
+    /// when using peak detection.
+    rms_coefficient: Option<f32>,
+    /// The envelope level a closed band gate opens at.
+    gate_open_level: f32,
+    /// The envelope level an open band gate closes at. Gating is disabled when this is 0.
+    gate_close_level: f32,
+    /// How much the band amplitudes are blurred with their neighbours, between 0 and 1.
+    spectral_smoothing: f32,
+}
+
+/// Settings for [`ChannelState::synthesize_sample()`] that stay the same for an entire block.
+#[derive(Debug, Clone, Copy)]
+struct SynthesisSettings {
+    /// Whether the carrier is the modulator itself.
+    self_vocoding: bool,
+    /// Whether every band is saturated before the bands are summed.
+    analog_emulation: bool,
+    /// Whether the carrier bands' phases are aligned at the band edges.
+    phase_align: bool,
+}
+
+/// The DSP state for a single channel. Every channel owns all of its state, so the channels never
+/// bleed into each other and this works the same way for all channel layouts. The only exception
+/// is the mono modulator mode, where every channel uses the first channel's analysis on purpose.
+#[derive(Debug, Clone)]
+struct ChannelState {
+    /// The modulator's filter bank. This is rebuilt by
+    /// [`StarlightVocoder::update_filter_bank()`] whenever the sample rate or the band layout
+    /// changes.
+    modulator_bank: FilterBank,
+    /// The same filters as `modulator_bank`, but with their own state for the carrier.
+    carrier_bank: FilterBank,
+    /// The carrier filter bank from before the last band layout change. Its output is faded out
+    /// while `carrier_bank` fades in.
+    previous_carrier_bank: FilterBank,
+    /// The gains `previous_carrier_bank`'s bands are weighted with during the crossfade. These
+    /// are the band amplitudes and band gains at the moment the layout changed.
+    previous_band_weights: Vec<f32>,
+    /// The number of samples left in the crossfade, and its total length.
+    crossfade_remaining: usize,
+    crossfade_length: usize,
+    /// The envelope follower state for every band. This is allocated for [`MAX_BANDS`] bands so
+    /// band count changes don't need to reallocate anything.
+    envelopes: Vec<f32>,
+    /// Every band's running mean square for the RMS envelope mode.
+    mean_squares: Vec<f32>,
+    /// Every band's held and decaying peak envelope.
+    band_tails: Vec<BandTail>,
+    /// Whether every band's gate is currently open.
+    gates_open: Vec<bool>,
+    /// The amplitudes the carrier's bands are shaped with. These are the envelopes after gating.
+    band_amplitudes: Vec<f32>,
+    sibilance_detector: SibilanceDetector,
+    transient_detector: TransientDetector,
+    /// Remove DC offsets from the channel's main input before it's analyzed, and from the final
+    /// output before it's limited.
+    input_dc_blocker: DcBlocker,
+    output_dc_blocker: DcBlocker,
+    /// The previous modulator sample, for the pre-emphasis filter.
+    pre_emphasis_state: f32,
+    /// The lowpass states that split the modulator and the carrier at the center of the band
+    /// range, for measuring their spectral tilt.
+    modulator_tilt_state: f32,
+    carrier_tilt_state: f32,
+    /// Removes rumble from the carrier before it's split into bands.
+    carrier_highpass: Biquad,
+    /// The white noise source that's mixed into the carrier during sibilance.
+    noise: NoiseGenerator,
+    /// The air band's filters for the modulator and the carrier, which pass everything above the
+    /// top band.
+    modulator_air_highpass: Biquad,
+    carrier_air_highpass: Biquad,
+    /// The air band's envelope follower, tracking the modulator's level above the top band.
+    air_envelope: f32,
+    /// This block's air band output, before it's delayed to line up with the wet signal.
+    air: Vec<f32>,
+    /// Delays the air band by the oversampling or FFT latency. The carrier it's computed from has
+    /// already been delayed by the lookahead time.
+    air_delay: DelayLine,
+    /// The FFT engine's state, used instead of the filter banks with [`Engine::Fft`] and
+    /// alongside them while blending the engines.
+    spectral: SpectralVocoder,
+    /// The FFT engine's band levels. These are kept apart from `band_amplitudes` so both engines
+    /// can run at the same time, and they're copied there when the FFT engine runs on its own.
+    spectral_levels: Vec<f32>,
+    /// The FFT engine's output for the current block.
+    spectral_output: Vec<f32>,
+    /// Delays the filter bank's output by the difference between the FFT and the oversampling
+    /// latencies while blending the engines.
+    filter_bank_delay: DelayLine,
+
+    modulator_upsampler: Upsampler,
+    carrier_upsampler: Upsampler,
+    output_downsampler: Downsampler,
+    /// Delays the carrier by the lookahead time, so the envelopes are ahead of the carrier.
+    carrier_delay: DelayLine,
+    /// Delays the dry signal by the lookahead time plus the oversampling or FFT latency so it
+    /// stays aligned with the wet signal.
+    dry_delay: DelayLine,
+    /// The lowpass states for the dry crossover's dry and wet signals. The wet signal's highpass is
+    /// the wet signal minus its lowpassed version, so the two halves sum back up to a flat
+    /// response.
+    crossover_dry_state: f32,
+    crossover_wet_state: f32,
+    /// The lowpass state for the carrier bass bypass. The vocoded carrier is the carrier minus
+    /// its lowpassed version, so the bypassed bass and the rest of the carrier sum back up to the
+    /// original carrier.
+    carrier_split_state: f32,
+    /// In mid/side mode the vocoded carrier is the mid or side signal, but the bypassed bass needs
+    /// to come from the channel's own carrier channel. This lowpasses that channel in mid/side
+    /// mode.
+    carrier_bypass_state: f32,
+    /// This block's bypassed carrier bass, before it's delayed to line up with the wet signal.
+    carrier_bass: Vec<f32>,
+    /// Delays the carrier bass by the same amount as the dry signal.
+    carrier_bass_delay: DelayLine,
+    /// Delays the input by the plugin's total latency while it's bypassed.
+    bypass_delay: DelayLine,
+    /// The ducker's envelope follower, tracking the modulator's overall level.
+    duck_envelope: f32,
+    /// The gain the ducker applies to every wet sample in the current block.
+    duck_gains: Vec<f32>,
+
+    // The band processing's input and output at the oversampled rate. These are large enough to
+    // hold a maximum size buffer at the highest oversampling factor.
+    modulator_buffer: Vec<f32>,
+    carrier_buffer: Vec<f32>,
+    output_buffer: Vec<f32>,
+}
+
+impl ChannelState {
+    /// Create the state for the channel at `channel_idx`. The index decides the noise generator's
+    /// seed, so the channels' noise is uncorrelated.
+    fn new(sample_rate: f32, max_buffer_size: usize, channel_idx: usize) -> Self {
+        let max_oversampled_buffer_size = max_buffer_size << oversampling::MAX_STAGES;
+        let max_dry_delay = lookahead_samples(MAX_LOOKAHEAD_MS, sample_rate)
+            + oversampling::latency_samples(oversampling::MAX_STAGES).max(spectral::LATENCY_SAMPLES)
+                as usize;
+
+        Self {
+            modulator_bank: FilterBank::default(),
+            carrier_bank: FilterBank::default(),
+            previous_carrier_bank: FilterBank::default(),
+            previous_band_weights: vec![0.0; MAX_BANDS],
+            crossfade_remaining: 0,
+            crossfade_length: 0,
+            envelopes: vec![0.0; MAX_BANDS],
+            mean_squares: vec![0.0; MAX_BANDS],
+            band_tails: vec![BandTail::default(); MAX_BANDS],
+            gates_open: vec![false; MAX_BANDS],
+            band_amplitudes: vec![0.0; MAX_BANDS],
+            sibilance_detector: SibilanceDetector::new(sample_rate),
+            transient_detector: TransientDetector::new(sample_rate),
+            input_dc_blocker: DcBlocker::new(INPUT_DC_BLOCKER_HZ, sample_rate),
+            output_dc_blocker: DcBlocker::new(OUTPUT_DC_BLOCKER_HZ, sample_rate),
+            pre_emphasis_state: 0.0,
+            modulator_tilt_state: 0.0,
+            carrier_tilt_state: 0.0,
+            carrier_highpass: Biquad::default(),
+            noise: NoiseGenerator::new(channel_idx as u32),
+            spectral: SpectralVocoder::default(),
+            spectral_levels: vec![0.0; MAX_BANDS],
+            spectral_output: vec![0.0; max_buffer_size],
+            filter_bank_delay: DelayLine::new(spectral::LATENCY_SAMPLES as usize),
+            modulator_air_highpass: Biquad::default(),
+            carrier_air_highpass: Biquad::default(),
+            air_envelope: 0.0,
+            air: vec![0.0; max_buffer_size],
+            air_delay: DelayLine::new(
+                oversampling::latency_samples(oversampling::MAX_STAGES)
+                    .max(spectral::LATENCY_SAMPLES) as usize,
+            ),
+
+            modulator_upsampler: Upsampler::default(),
+            carrier_upsampler: Upsampler::default(),
+            output_downsampler: Downsampler::default(),
+            carrier_delay: DelayLine::new(lookahead_samples(MAX_LOOKAHEAD_MS, sample_rate)),
+            dry_delay: DelayLine::new(max_dry_delay),
+            crossover_dry_state: 0.0,
+            crossover_wet_state: 0.0,
+            carrier_split_state: 0.0,
+            carrier_bypass_state: 0.0,
+            carrier_bass: vec![0.0; max_buffer_size],
+            carrier_bass_delay: DelayLine::new(max_dry_delay),
+            bypass_delay: DelayLine::new(
+                max_dry_delay + lookahead_samples(LIMITER_LOOKAHEAD_MS, sample_rate).max(1),
+            ),
+            duck_envelope: 0.0,
+            duck_gains: vec![1.0; max_buffer_size],
+
+            modulator_buffer: vec![0.0; max_oversampled_buffer_size],
+            carrier_buffer: vec![0.0; max_oversampled_buffer_size],
+            output_buffer: vec![0.0; max_oversampled_buffer_size],
+        }
+    }
+
+    /// Update the envelope followers with the modulator sample at `sample_idx` in
+    /// `modulator_buffer`, and compute the band amplitudes from them. The modulator band's
+    /// amplitude envelope is its rectified or RMS level, smoothed with the attack and release
+    /// times.
+    fn analyze_sample(&mut self, sample_idx: usize, settings: EnvelopeSettings) {
+        let sample = self.modulator_buffer[sample_idx];
+        for (
+            (((((band_sample, envelope), (mean_square, tail)), gate_open), amplitude), attack),
+            release,
+        ) in self
+            .modulator_bank
+            .process_sample(sample)
+            .zip(self.envelopes.iter_mut())
+            .zip(self.mean_squares.iter_mut().zip(self.band_tails.iter_mut()))
+            .zip(self.gates_open.iter_mut())
+            .zip(self.band_amplitudes.iter_mut())
+            .zip(settings.attack_rates)
+            .zip(settings.release_rates)
+        {
+            let level = match settings.rms_coefficient {
+                Some(rms_coefficient) => {
+                    *mean_square = flush_denormal(
+                        *mean_square + rms_coefficient * (band_sample * band_sample - *mean_square),
+                    );
+                    // The RMS level is scaled so a sine wave ends up at the same level as with
+                    // peak detection
+                    (*mean_square * 2.0).sqrt()
+                }
+                None => band_sample.abs(),
+            };
+            let rate = if level > *envelope { attack } else { release } * settings.rate_scale;
+            *envelope = flush_denormal(match settings.shape {
+                EnvelopeShape::Exponential => *envelope + rate * (level - *envelope),
+                EnvelopeShape::Linear => *envelope + (level - *envelope).clamp(-rate, rate),
+            });
+            let extended_envelope = tail.process(
+                *envelope,
+                settings.hold_samples,
+                settings.decay_rate * settings.rate_scale,
+            );
+
+            *gate_open = if *gate_open {
+                extended_envelope >= settings.gate_close_level
+            } else {
+                extended_envelope >= settings.gate_open_level
+            };
+            *amplitude = if *gate_open { extended_envelope } else { 0.0 };
+        }
+
+        // This is a three band moving average across the bands, faded in by the smoothing amount.
+        // The outermost bands reuse their own amplitude in place of the missing neighbour.
+        if settings.spectral_smoothing > 0.0 {
+            let amplitudes = &mut self.band_amplitudes[..self.modulator_bank.num_bands()];
+            let mut previous = amplitudes.first().copied().unwrap_or_default();
+            for band_idx in 0..amplitudes.len() {
+                let current = amplitudes[band_idx];
+                let next = amplitudes.get(band_idx + 1).copied().unwrap_or(current);
+                let average = (previous + current + next) / 3.0;
+
+                amplitudes[band_idx] = current + (average - current) * settings.spectral_smoothing;
+                previous = current;
+            }
+        }
+    }
+
+    /// Shape the carrier sample at `sample_idx` in `carrier_buffer` with the band amplitudes,
+    /// apply the band gains, and write the result to `output_buffer`. This uses the analysis from
+    /// `shared_analysis` if set, and this channel's own analysis otherwise. Without a formant
+    /// shift every carrier band uses the amplitude from the same band in the modulator.
+    ///
+    /// When the carrier is the modulator itself every carrier band already follows its envelope,
+    /// so applying the amplitudes on top of that would square the band levels. With
+    /// `self_vocoding` set the carrier bands are divided by their envelopes first.
+    ///
+    /// With analog emulation enabled every band goes through a soft clipper before the bands are
+    /// summed.
+    fn synthesize_sample(
+        &mut self,
+        sample_idx: usize,
+        shared_analysis: Option<&ChannelState>,
+        formant_map: &[usize],
+        band_gains: &[f32],
+        settings: SynthesisSettings,
+    ) {
+        let sample = self.carrier_buffer[sample_idx];
+        let (amplitudes, envelopes) = match shared_analysis {
+            Some(analysis) => (&analysis.band_amplitudes, &analysis.envelopes),
+            None => (&self.band_amplitudes, &self.envelopes),
+        };
+        let shape_band = |band_idx: usize, band_sample: f32| {
+            let source_band_idx = formant_map[band_idx];
+            let amplitude = if settings.self_vocoding {
+                amplitudes[source_band_idx] / envelopes[band_idx].max(SELF_VOCODING_ENVELOPE_FLOOR)
+            } else {
+                amplitudes[source_band_idx]
+            };
+
+            let band_output = band_sample * amplitude * band_gains[band_idx];
+            if settings.analog_emulation {
+                // The overlapping filters let more of the carrier through, which is compensated
+                // for here so enabling this doesn't make everything louder
+                (band_output * ANALOG_DRIVE).tanh() / (ANALOG_DRIVE * ANALOG_BAND_OVERLAP.sqrt())
+            } else {
+                band_output
+            }
+        };
+
+        let output = if settings.phase_align {
+            self.carrier_bank
+                .process_sample_phase_aligned(sample)
+                .enumerate()
+                .map(|(band_idx, band_sample)| shape_band(band_idx, band_sample))
+                .sum()
+        } else {
+            self.carrier_bank
+                .process_sample(sample)
+                .enumerate()
+                .map(|(band_idx, band_sample)| shape_band(band_idx, band_sample))
+                .sum()
+        };
+
+        // The old filter bank keeps running with the envelopes it had when it was replaced, which
+        // is close enough for the few milliseconds it takes to fade out
+        self.output_buffer[sample_idx] = if self.crossfade_remaining > 0 {
+            let previous_output: f32 = self
+                .previous_carrier_bank
+                .process_sample(sample)
+                .zip(&self.previous_band_weights)
+                .map(|(band_sample, weight)| band_sample * weight)
+                .sum();
+            let t = self.crossfade_remaining as f32 / self.crossfade_length as f32;
+            self.crossfade_remaining -= 1;
+
+            output + (previous_output - output) * t
+        } else {
+            output
+        };
+    }
+
+    fn reset(&mut self) {
+        self.modulator_bank.reset();
+        self.carrier_bank.reset();
+        self.previous_carrier_bank.reset();
+        self.crossfade_remaining = 0;
+        self.envelopes.fill(0.0);
+        self.mean_squares.fill(0.0);
+        self.band_tails.fill(BandTail::default());
+        self.gates_open.fill(false);
+        self.band_amplitudes.fill(0.0);
+        self.sibilance_detector.reset();
+        self.transient_detector.reset();
+        self.input_dc_blocker.reset();
+        self.output_dc_blocker.reset();
+        self.pre_emphasis_state = 0.0;
+        self.modulator_tilt_state = 0.0;
+        self.carrier_tilt_state = 0.0;
+        self.carrier_highpass.reset();
+        self.noise.reset();
+        self.spectral.reset();
+        self.spectral_levels.fill(0.0);
+        self.filter_bank_delay.reset();
+        self.modulator_air_highpass.reset();
+        self.carrier_air_highpass.reset();
+        self.air_envelope = 0.0;
+        self.air_delay.reset();
+
+        self.modulator_upsampler.reset();
+        self.carrier_upsampler.reset();
+        self.output_downsampler.reset();
+        self.carrier_delay.reset();
+        self.dry_delay.reset();
+        self.crossover_dry_state = 0.0;
+        self.crossover_wet_state = 0.0;
+        self.carrier_split_state = 0.0;
+        self.carrier_bypass_state = 0.0;
+        self.carrier_bass_delay.reset();
+        self.bypass_delay.reset();
+        self.duck_envelope = 0.0;
+    }
+}
+
+impl Default for StarlightVocoder {
+    fn default() -> Self {
+        Self {
+            vocoder: Vocoder::unconfigured(),
+            latency_samples: 0,
+        }
+    }
+}
+
+impl Default for StlVocoderParams {
+    fn default() -> Self {
+        Self {
+            editor_state: editor::default_state(),
+            settings: RwLock::new(PersistedSettings::default()),
+
+            // The logarithmic smoothers can't start or end at 0 Hz, and the bands can't start below
+            // `MIN_BAND_FREQUENCY` anyways. The skew gives the low frequencies more resolution, and
+            // the formatters already include the unit, switching to kHz above 1000 Hz.
+            low_freq_cutoff: FloatParam::new(
+                "Formant lower end",
+                300f32,
+                FloatRange::Skewed {
+                    min: MIN_BAND_FREQUENCY,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(100.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            high_freq_cutoff: FloatParam::new(
+                "Formant upper end",
+                3400f32,
+                FloatRange::Skewed {
+                    min: MIN_BAND_FREQUENCY,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(100.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz()),
+            bands: IntParam::new(
+                "Number of Bands",
+                20,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_BANDS as i32,
+                },
+            ),
+            spacing: EnumParam::new("Band Spacing", BandSpacing::Logarithmic),
+            engine: EnumParam::new("Engine", Engine::FilterBank),
+            engine_blend: FloatParam::new(
+                "Engine Blend",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            fft_window: EnumParam::new("FFT Window", FftWindow::Hann),
+            fft_overlap: EnumParam::new("FFT Overlap", FftOverlap::X4),
+            dry_wet: FloatParam::new("Dry/Wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0))
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            dry_crossover: FloatParam::new(
+                "Dry Crossover",
+                DRY_CROSSOVER_OFF_HZ,
+                FloatRange::Skewed {
+                    min: DRY_CROSSOVER_OFF_HZ,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_value_to_string(v2s_hz_or_off(DRY_CROSSOVER_OFF_HZ))
+            .with_string_to_value(s2v_hz_or_off(DRY_CROSSOVER_OFF_HZ)),
+            output_gain: FloatParam::new(
+                "Output Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            carrier_gain: FloatParam::new(
+                "Carrier Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            input_trim: FloatParam::new(
+                "Input Trim",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-30.0),
+                    max: util::db_to_gain(30.0),
+                    factor: FloatRange::gain_skew_factor(-30.0, 30.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            input_normalize: BoolParam::new("Auto Normalize", false),
+            saturation: FloatParam::new(
+                "Saturation",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            limiter: BoolParam::new("Limiter", true),
+            attack_ms: FloatParam::new(
+                "Attack",
+                5.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            release_ms: FloatParam::new(
+                "Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            envelope_mode: EnumParam::new("Envelope Mode", EnvelopeMode::Peak),
+            envelope_shape: EnumParam::new("Envelope Shape", EnvelopeShape::Exponential),
+            env_tilt: FloatParam::new(
+                "Envelope Tilt",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            band_hold_ms: FloatParam::new(
+                "Band Hold",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            band_decay_ms: FloatParam::new(
+                "Band Decay",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 10000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            lookahead_ms: FloatParam::new(
+                "Lookahead",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_LOOKAHEAD_MS,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            carrier_source: EnumParam::new("Carrier Source", CarrierSource::Sidechain),
+            input_routing: EnumParam::new("Input Routing", InputRouting::MainIsModulator),
+            synth_attack_ms: FloatParam::new(
+                "Synth Attack",
+                5.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            synth_release_ms: FloatParam::new(
+                "Synth Release",
+                100.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            max_voices: IntParam::new(
+                "Max Voices",
+                synth::MAX_VOICES as i32,
+                IntRange::Linear {
+                    min: 1,
+                    max: synth::MAX_VOICES as i32,
+                },
+            ),
+            robot_freq: FloatParam::new(
+                "Robot Frequency",
+                110.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(1)),
+            carrier_hp: FloatParam::new(
+                "Carrier Highpass",
+                80.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            carrier_bass_bypass: FloatParam::new(
+                "Carrier Bass Bypass",
+                CARRIER_BASS_BYPASS_OFF_HZ,
+                FloatRange::Skewed {
+                    min: CARRIER_BASS_BYPASS_OFF_HZ,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_value_to_string(v2s_hz_or_off(CARRIER_BASS_BYPASS_OFF_HZ))
+            .with_string_to_value(s2v_hz_or_off(CARRIER_BASS_BYPASS_OFF_HZ)),
+            bypass: BoolParam::new("Bypass", false)
+                .with_value_to_string(formatters::v2s_bool_bypass())
+                .with_string_to_value(formatters::s2v_bool_bypass())
+                .make_bypass(),
+            sibilance_amount: FloatParam::new(
+                "Sibilance",
+                0.3,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            transient_amount: FloatParam::new(
+                "Transients",
+                0.2,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            air_amount: FloatParam::new("Air", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            pre_emphasis: FloatParam::new(
+                "Pre-Emphasis",
+                0.3,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            filter_order: IntParam::new(
+                "Filter Order",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_FILTER_ORDER as i32,
+                },
+            ),
+            auto_gain: BoolParam::new("Auto Gain", false),
+            min_band_width: FloatParam::new(
+                "Min Band Width",
+                0.01,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.25,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(1))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            eco_mode: BoolParam::new("Eco Mode", false),
+            analog_emulation: BoolParam::new("Analog Emulation", false),
+            // The filters are designed in double precision, so even the narrowest bands at the
+            // highest order stay stable
+            band_q: FloatParam::new(
+                "Band Q",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            analysis_q: FloatParam::new(
+                "Analysis Q",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            synthesis_q: FloatParam::new(
+                "Synthesis Q",
+                1.0,
+                FloatRange::Skewed {
+                    min: 0.25,
+                    max: 4.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            phase_align: BoolParam::new("Phase Align", false),
+            formant_shift: FloatParam::new(
+                "Formant Shift",
+                0.0,
+                FloatRange::Linear {
+                    min: -12.0,
+                    max: 12.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" st"),
+            lfo_rate: FloatParam::new(
+                "LFO Rate",
+                0.5,
+                FloatRange::Skewed {
+                    min: 0.01,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            lfo_depth: FloatParam::new(
+                "LFO Depth",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 12.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" st"),
+            oversampling: EnumParam::new("Oversampling", Oversampling::X1),
+            // Most voices are recorded in mono or panned to the center anyways
+            modulator_mono: BoolParam::new("Mono Modulator", true),
+            env_link: BoolParam::new("Link Envelopes", true),
+            tilt: FloatParam::new(
+                "Tilt",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            spectral_match: FloatParam::new(
+                "Spectral Match",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            stereo_spread: FloatParam::new(
+                "Stereo Spread",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            ms_mode: BoolParam::new("Mid/Side Carrier", false),
+            mono_safe: BoolParam::new("Mono Safe", false),
+            mono_output: BoolParam::new("Mono Output", false),
+            freeze: BoolParam::new("Freeze", false),
+            gate_sync: EnumParam::new("Gate Sync", GateSync::Off),
+            spectral_smoothing: FloatParam::new(
+                "Spectral Smoothing",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+            gate_threshold: FloatParam::new(
+                "Gate Threshold",
+                GATE_THRESHOLD_OFF_DB,
+                FloatRange::Linear {
+                    min: GATE_THRESHOLD_OFF_DB,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(Arc::new(|value| {
+                if value <= GATE_THRESHOLD_OFF_DB {
+                    String::from("Off")
+                } else {
+                    format!("{value:.1}")
+                }
+            })),
+            // The bands are shown one-indexed
+            solo_band: IntParam::new(
+                "Solo Band",
+                -1,
+                IntRange::Linear {
+                    min: -1,
+                    max: MAX_BANDS as i32 - 1,
+                },
+            )
+            .with_value_to_string(Arc::new(|value| {
+                if value < 0 {
+                    String::from("Off")
+                } else {
+                    (value + 1).to_string()
+                }
+            }))
+            .with_string_to_value(Arc::new(|string| {
+                let string = string.trim();
+                if string.eq_ignore_ascii_case("off") {
+                    Some(-1)
+                } else {
+                    string.parse::<i32>().ok().map(|band| band - 1)
+                }
+            })),
+            duck_depth: FloatParam::new(
+                "Duck Depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            band_gains: std::array::from_fn(BandGainParams::new),
+            band_pans: std::array::from_fn(BandPanParams::new),
+        }
+    }
+}
+
+/// Detects sibilance ("s", "t", "sh", etc.) by comparing the modulator's high frequency energy to
+/// its overall energy.
+#[derive(Debug, Clone)]
+struct SibilanceDetector {
+    highpass: Biquad,
+    high_envelope: f32,
+    full_envelope: f32,
+}
+
+impl SibilanceDetector {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            // The detection frequency can't be anywhere near the Nyquist frequency
+            highpass: Biquad::highpass(
+                sample_rate,
+                SIBILANCE_FREQUENCY.min(sample_rate * 0.4),
+                FRAC_1_SQRT_2,
+            ),
+            high_envelope: 0.0,
+            full_envelope: 0.0,
+        }
+    }
+
+    /// Analyze the next modulator sample. Returns how sibilant the modulator currently is, from 0
+    /// (no high frequency content) to 1 (the high frequencies dominate).
+    fn process(&mut self, input: f32, coefficient: f32) -> f32 {
+        let high = self.highpass.process(f64::from(input)) as f32;
+        self.high_envelope =
+            flush_denormal(self.high_envelope + coefficient * (high.abs() - self.high_envelope));
+        self.full_envelope =
+            flush_denormal(self.full_envelope + coefficient * (input.abs() - self.full_envelope));
+
+        if self.full_envelope <= f32::EPSILON {
+            return 0.0;
+        }
+
+        // Voiced sounds have some high frequency content too, so this only starts kicking in once
+        // the high frequencies make up a significant portion of the signal
+        let high_ratio = self.high_envelope / self.full_envelope;
+        ((high_ratio - 0.3) / 0.4).clamp(0.0, 1.0)
+    }
+
+    fn reset(&mut self) {
+        self.highpass.reset();
+        self.high_envelope = 0.0;
+        self.full_envelope = 0.0;
+    }
+}
+
+/// Detects transients by comparing a fast envelope follower to a slow one. Both follow the
+/// modulator's level, but the fast one catches up with a sudden jump in level well before the slow
+/// one does.
+#[derive(Debug, Clone)]
+struct TransientDetector {
+    fast_attack: f32,
+    slow_attack: f32,
+    release: f32,
+    fast_envelope: f32,
+    slow_envelope: f32,
+}
+
+impl TransientDetector {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            fast_attack: one_pole_coefficient(TRANSIENT_FAST_ATTACK_MS, sample_rate),
+            slow_attack: one_pole_coefficient(TRANSIENT_SLOW_ATTACK_MS, sample_rate),
+            release: one_pole_coefficient(TRANSIENT_RELEASE_MS, sample_rate),
+            fast_envelope: 0.0,
+            slow_envelope: 0.0,
+        }
+    }
+
+    /// Analyze the next modulator sample. Returns how far the fast envelope is above the slow
+    /// envelope relative to the slow envelope, up to [`MAX_TRANSIENT_BOOST`]. This is 0 while the
+    /// level is steady or falling.
+    fn process(&mut self, input: f32) -> f32 {
+        let level = input.abs();
+        let release = self.release;
+        let follow = |envelope: f32, attack: f32| {
+            let coefficient = if level > envelope { attack } else { release };
+            flush_denormal(envelope + coefficient * (level - envelope))
+        };
+        self.fast_envelope = follow(self.fast_envelope, self.fast_attack);
+        self.slow_envelope = follow(self.slow_envelope, self.slow_attack);
+
+        (self.fast_envelope / self.slow_envelope.max(TAIL_THRESHOLD) - 1.0)
+            .clamp(0.0, MAX_TRANSIENT_BOOST)
+    }
+
+    fn reset(&mut self) {
+        self.fast_envelope = 0.0;
+        self.slow_envelope = 0.0;
+    }
+}
+
+/// Extends a band's envelope past its peaks. The tail holds a peak for a while and then decays
+/// from it on its own schedule, no matter how quickly the envelope itself falls.
+#[derive(Debug, Clone, Copy, Default)]
+struct BandTail {
+    level: f32,
+    hold_remaining: u32,
 }
 
-impl Default for StarlightVocoder {
-    fn default() -> Self {
-        Self {
-            params: Arc::new(StlVocoderParams::default()),
-            // buffer_config: BufferConfig {
-            //     sample_rate: 1.0,
-            //     min_buffer_size: None,
-            //     max_buffer_size: 0,
-            //     process_mode: ProcessMode::Realtime,
-            // },
+impl BandTail {
+    /// Update the tail with the band's current envelope. Returns the extended envelope, which is
+    /// the larger of the two. The tail is fully disabled with no hold time and a decay rate of 1.
+    fn process(&mut self, envelope: f32, hold_samples: u32, decay_rate: f32) -> f32 {
+        if envelope >= self.level {
+            self.level = envelope;
+            self.hold_remaining = hold_samples;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.level = flush_denormal(self.level - self.level * decay_rate);
+        }
+
+        envelope.max(self.level)
+    }
+}
+
+/// A first-order DC blocking highpass filter.
+#[derive(Debug, Clone)]
+struct DcBlocker {
+    /// The pole's radius. The closer this is to 1, the lower the cutoff frequency.
+    coefficient: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl DcBlocker {
+    fn new(cutoff_hz: f32, sample_rate: f32) -> Self {
+        Self {
+            coefficient: (-TAU * cutoff_hz / sample_rate).exp(),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output =
+            flush_denormal(input - self.previous_input + self.coefficient * self.previous_output);
+        self.previous_input = input;
+        self.previous_output = output;
+
+        output
+    }
+
+    fn reset(&mut self) {
+        self.previous_input = 0.0;
+        self.previous_output = 0.0;
+    }
+}
+
+/// A delay line with a fixed maximum delay.
+#[derive(Debug, Clone)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    write_pos: usize,
+}
+
+impl DelayLine {
+    fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1],
+            write_pos: 0,
+        }
+    }
+
+    /// Write `input` to the delay line and return the sample from `delay` samples ago. The delay
+    /// is clamped to the maximum delay.
+    fn process(&mut self, input: f32, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[self.write_pos] = input;
+        let output = self.buffer[(self.write_pos + len - delay.min(len - 1)) % len];
+        self.write_pos = (self.write_pos + 1) % len;
+
+        output
+    }
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+/// A cheap xorshift based white noise generator.
+#[derive(Debug, Clone)]
+struct NoiseGenerator {
+    /// The state the generator starts from, and returns to when it's reset.
+    seed: u32,
+    state: u32,
+}
+
+impl NoiseGenerator {
+    /// Create a noise generator whose sequence is determined by `seed`. Different seeds result in
+    /// uncorrelated noise.
+    fn new(seed: u32) -> Self {
+        // This spreads neighbouring seeds apart. Xorshift gets stuck at 0, so that state needs to
+        // be avoided.
+        let seed = (0x9E37_79B9 ^ seed.wrapping_mul(0x85EB_CA6B)).max(1);
+
+        Self { seed, state: seed }
+    }
+
+    /// Produce the next noise sample in `[-1, 1]`.
+    fn next_sample(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        (self.state as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn reset(&mut self) {
+        self.state = self.seed;
+    }
+}
+
+/// The highest absolute sample value in any of `channels`.
+fn buffer_peak(channels: &[&mut [f32]]) -> f32 {
+    channels
+        .iter()
+        .flat_map(|channel| channel.iter())
+        .fold(0.0, |peak, sample| peak.max(sample.abs()))
+}
+
+/// Decode mid and side signals back to left and right in place, which exactly undoes the mid/side
+/// encoding.
+fn decode_mid_side(mid: &mut [f32], side: &mut [f32]) {
+    for (m, s) in mid.iter_mut().zip(side) {
+        (*m, *s) = (*m + *s, *m - *s);
+    }
+}
+
+/// Find the two of `num_points` control points spread out evenly over `num_bands` bands that
+/// band `band_idx` falls between. Returns the lower and upper point's indices, and how far the
+/// band is from the lower point to the upper point.
+fn control_points(band_idx: usize, num_bands: usize, num_points: usize) -> (usize, usize, f32) {
+    let position = if num_bands > 1 {
+        band_idx as f32 * (num_points - 1) as f32 / (num_bands - 1) as f32
+    } else {
+        0.0
+    };
+    let lower = (position as usize).min(num_points - 1);
+    let upper = (lower + 1).min(num_points - 1);
+
+    (lower, upper, position - lower as f32)
+}
+
+/// Format a frequency in Hz, or as "Off" at or below `off_hz`. The unit is part of the string
+/// since it doesn't apply to "Off".
+fn v2s_hz_or_off(off_hz: f32) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
+    Arc::new(move |value| {
+        if value <= off_hz {
+            String::from("Off")
+        } else {
+            format!("{value:.0} Hz")
+        }
+    })
+}
+
+/// Parses a parameter's value from a string, the type [`FloatParam::with_string_to_value()`] takes.
+type StringToValue = Arc<dyn Fn(&str) -> Option<f32> + Send + Sync>;
+
+/// The counterpart to [`v2s_hz_or_off()`].
+fn s2v_hz_or_off(off_hz: f32) -> StringToValue {
+    let hz_to_value = formatters::s2v_f32_hz_then_khz();
+    Arc::new(move |string| {
+        if string.trim().eq_ignore_ascii_case("off") {
+            Some(off_hz)
+        } else {
+            hz_to_value(string)
+        }
+    })
+}
+
+/// The lookahead time in samples.
+fn lookahead_samples(lookahead_ms: f32, sample_rate: f32) -> usize {
+    (lookahead_ms / 1000.0 * sample_rate).round() as usize
+}
+
+/// Compute the coefficient for a one-pole smoother that reaches roughly 63% of its target after
+/// `time_ms` milliseconds.
+fn one_pole_coefficient(time_ms: f32, sample_rate: f32) -> f32 {
+    1.0 - (-1.0 / (time_ms / 1000.0 * sample_rate)).exp()
+}
+
+impl Vocoder {
+    /// A vocoder that hasn't been initialized yet, so it doesn't have any channels.
+    fn unconfigured() -> Self {
+        Self {
+            params: Arc::new(StlVocoderParams::default()),
+            band_levels: (0..MAX_BANDS).map(|_| AtomicF32::new(0.0)).collect(),
+            effective_bands: Arc::new(AtomicUsize::new(0)),
+            input_peak: Arc::new(AtomicF32::new(0.0)),
+            output_peak: Arc::new(AtomicF32::new(0.0)),
+            correlation: Arc::new(AtomicF32::new(1.0)),
+
+            sample_rate: 44100.0,
+            channels: Vec::new(),
+            filter_bank_layout: BandLayout::default(),
+            filter_bank_oversampling_stages: 0,
+            filter_bank_min_band_width: 0.0,
+            prototype_bank: FilterBank::default(),
+            carrier_prototype_bank: FilterBank::default(),
+            band_gain_trims: [1.0; NUM_BAND_GAINS],
+            band_pans: [0.0; NUM_BAND_PANS],
+            stereo_spread: 0.0,
+            mono_safe_width: 1.0,
+            mono_output_amount: 0.0,
+            band_gain_targets: [vec![1.0; MAX_BANDS], vec![1.0; MAX_BANDS]],
+            band_gains: [vec![1.0; MAX_BANDS], vec![1.0; MAX_BANDS]],
+            band_gain_steps: [vec![0.0; MAX_BANDS], vec![0.0; MAX_BANDS]],
+            band_attack_rates: vec![0.0; MAX_BANDS],
+            band_release_rates: vec![0.0; MAX_BANDS],
+            formant_map: Vec::with_capacity(MAX_BANDS),
+            formant_map_shift: 0.0,
+            lfo_phase: 0.0,
+            lfo_value: 0.0,
+            carrier_highpass_frequency: 0.0,
+            air_frequency: 0.0,
+            freeze_amount: 0.0,
+            normalize_mean_square: util::db_to_gain(NORMALIZE_TARGET_DB).powi(2),
+            normalize_gain: 1.0,
+            carrier_tilt_difference_db: 0.0,
+            eco_active: false,
+            eco_switch_gain: 1.0,
+            sysex_band_gains: vec![1.0; MAX_BANDS],
+            band_curve_gains: vec![1.0; MAX_BANDS],
+            sysex_freeze: None,
+            gate_sync_gain: 1.0,
+            engines: ActiveEngines {
+                filter_bank: true,
+                fft: false,
+            },
+            silent_samples: 0,
+
+            mono_modulator_scratch: Vec::new(),
+            modulator_scratch: Vec::new(),
+            carrier_scratch: Vec::new(),
+            output_scratch: Vec::new(),
+            dry_wet_scratch: Vec::new(),
+            engine_blend_scratch: Vec::new(),
+            gate_sync_scratch: Vec::new(),
+            output_gain_scratch: Vec::new(),
+            carrier_gain_scratch: Vec::new(),
+            modulator_gain_scratch: Vec::new(),
+            saturation_scratch: Vec::new(),
+
+            limiter: Limiter::new(
+                0,
+                44100.0,
+                util::db_to_gain(LIMITER_CEILING_DB),
+                LIMITER_LOOKAHEAD_MS,
+                LIMITER_RELEASE_MS,
+            ),
+            limiter_active: true,
+
+            process_mode: ProcessMode::Realtime,
+            max_block_size: 0,
+
+            synth: CarrierSynth::default(),
+            robot_oscillator: BuzzOscillator::default(),
+            synth_scratch: Vec::new(),
+            main_input_scratch: Vec::new(),
+        }
+    }
+
+    /// Create a vocoder for a single channel, with the parameters at their default values.
+    pub fn new(config: VocoderConfig) -> Self {
+        let mut vocoder = Self::unconfigured();
+        vocoder.initialize(&config, 1);
+        vocoder.reset();
+
+        vocoder
+    }
+
+    /// Vocode `modulator` with `carrier` and return the result, which is as long as the
+    /// modulator. A carrier that's shorter than the modulator is padded with silence. All state
+    /// carries over between calls, so a long recording can be processed in any number of
+    /// pieces. This allocates the output, so it shouldn't be used on a realtime thread.
+    pub fn process_block(&mut self, modulator: &[f32], carrier: &[f32]) -> Vec<f32> {
+        let mut output = modulator.to_vec();
+        let mut carrier = carrier.to_vec();
+        carrier.resize(modulator.len(), 0.0);
+
+        for (output_block, carrier_block) in output
+            .chunks_mut(self.max_block_size)
+            .zip(carrier.chunks(self.max_block_size))
+        {
+            self.process(&mut [output_block], Some(&[carrier_block]), None, || None);
+        }
+
+        output
+    }
+
+    /// Load a custom band gain curve from the file at `path`. This can be an impulse response in a
+    /// WAV file, or a text file with a frequency in Hz and a gain in dB on every line. Every band's
+    /// gain is taken from the curve at the band's center frequency, so this works for any band
+    /// count. The curve is saved with the plugin's state and applied on top of the band gain
+    /// parameters.
+    pub fn load_band_gain_curve(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.params.load_band_gain_curve(path.as_ref())
+    }
+
+    /// Remove the custom band gain curve, if one is loaded.
+    pub fn clear_band_gain_curve(&self) {
+        self.params.clear_band_gain_curve();
+    }
+
+    /// A shared handle to the current envelope level of every band, for displaying the bands
+    /// outside of the built-in editor. There's always one entry for each of the [`MAX_BANDS`]
+    /// bands, and the entries for bands past the current band count are zero. The levels are
+    /// linear amplitudes, updated with relaxed atomic stores at the end of every processed block,
+    /// so they can be read from any thread without locking.
+    pub fn band_levels(&self) -> Arc<[AtomicF32]> {
+        self.band_levels.clone()
+    }
+
+    /// The total latency introduced by the current settings, in samples. The filter bank and the
+    /// envelope followers are causal, so with the filter bank engine only the lookahead and the
+    /// oversampling filters add latency. The FFT engine adds its frame size instead, and so does
+    /// blending the two engines. The output limiter adds its own lookahead on top of that.
+    pub fn latency_samples(&self) -> u32 {
+        let limiter_latency = if self.params.limiter.value() {
+            self.limiter.latency_samples()
+        } else {
+            0
+        };
+
+        lookahead_samples(self.params.lookahead_ms.value(), self.sample_rate) as u32
+            + self.engine_latency_samples(self.active_engines())
+            + limiter_latency
+    }
+
+    /// Clear all state that depends on previous input, so the next sample is processed as if the
+    /// vocoder was only fed silence.
+    pub fn reset(&mut self) {
+        self.reset_dsp_state();
+        self.synth.reset();
+        self.robot_oscillator.reset();
+        self.limiter.reset();
+        self.lfo_phase = 0.0;
+        self.lfo_value = 0.0;
+        self.freeze_amount = if self.freeze() { 1.0 } else { 0.0 };
+        self.normalize_mean_square = util::db_to_gain(NORMALIZE_TARGET_DB).powi(2);
+        self.normalize_gain = 1.0;
+        self.carrier_tilt_difference_db = 0.0;
+        self.mono_output_amount = if self.params.mono_output.value() {
+            1.0
+        } else {
+            0.0
+        };
+        self.eco_active = self.eco_mode();
+        self.eco_switch_gain = 1.0;
+        self.gate_sync_gain = 1.0;
+        self.engines = self.active_engines();
+        self.silent_samples = 0;
+
+        // Nothing should still be fading towards values set before the reset
+        for param in [
+            &self.params.low_freq_cutoff,
+            &self.params.high_freq_cutoff,
+            &self.params.dry_wet,
+            &self.params.engine_blend,
+            &self.params.output_gain,
+            &self.params.carrier_gain,
+            &self.params.input_trim,
+            &self.params.saturation,
+            &self.params.stereo_spread,
+            &self.params.robot_freq,
+        ]
+        .into_iter()
+        .chain(
+            self.params
+                .band_gains
+                .iter()
+                .map(|band_gain| &band_gain.gain),
+        )
+        .chain(self.params.band_pans.iter().map(|band_pan| &band_pan.pan))
+        {
+            param.smoothed.reset(param.value());
+        }
+        for (trim, band_gain) in self.band_gain_trims.iter_mut().zip(&self.params.band_gains) {
+            *trim = band_gain.gain.value();
+        }
+        for (pan, band_pan) in self.band_pans.iter_mut().zip(&self.params.band_pans) {
+            *pan = band_pan.pan.value();
+        }
+        self.stereo_spread = self.params.stereo_spread.value();
+        self.update_band_gains();
+        self.snap_band_gains();
+
+        for level in self
+            .band_levels
+            .iter()
+            .chain([&*self.input_peak, &*self.output_peak])
+        {
+            level.store(0.0, Ordering::Relaxed);
+        }
+        self.correlation.store(1.0, Ordering::Relaxed);
+        self.mono_safe_width = 1.0;
+    }
+
+    /// Allocate the state for `num_channels` channels and compute everything that depends on the
+    /// sample rate. The rest of the state is kept, so this can be called again when the host
+    /// reconfigures the plugin.
+    fn initialize(&mut self, config: &VocoderConfig, num_channels: usize) {
+        self.sample_rate = config.sample_rate;
+        self.process_mode = if config.offline {
+            ProcessMode::Offline
+        } else {
+            ProcessMode::Realtime
+        };
+        if self.process_mode == ProcessMode::Offline {
+            nih_log!("Rendering offline, switching to the higher quality settings");
+        }
+
+        let max_buffer_size = config.max_block_size.max(1);
+        self.max_block_size = max_buffer_size;
+        self.channels = (0..num_channels)
+            .map(|channel_idx| ChannelState::new(self.sample_rate, max_buffer_size, channel_idx))
+            .collect();
+
+        self.mono_modulator_scratch.resize(max_buffer_size, 0.0);
+        self.modulator_scratch.reserve(max_buffer_size);
+        self.carrier_scratch.reserve(max_buffer_size);
+        self.output_scratch.resize(max_buffer_size, 0.0);
+        self.dry_wet_scratch.resize(max_buffer_size, 0.0);
+        self.engine_blend_scratch.resize(max_buffer_size, 0.0);
+        self.gate_sync_scratch.resize(max_buffer_size, 0.0);
+        self.output_gain_scratch.resize(max_buffer_size, 0.0);
+        self.carrier_gain_scratch.resize(max_buffer_size, 0.0);
+        self.modulator_gain_scratch.resize(max_buffer_size, 0.0);
+        self.saturation_scratch.resize(max_buffer_size, 0.0);
+        self.synth_scratch.resize(max_buffer_size, 0.0);
+        // These are cleared and refilled for every block, which never needs to grow them
+        self.main_input_scratch = (0..num_channels)
+            .map(|_| Vec::with_capacity(max_buffer_size))
+            .collect();
+
+        self.synth.set_sample_rate(self.sample_rate);
+        self.robot_oscillator.set_sample_rate(self.sample_rate);
+        self.limiter = Limiter::new(
+            num_channels,
+            self.sample_rate,
+            util::db_to_gain(LIMITER_CEILING_DB),
+            LIMITER_LOOKAHEAD_MS,
+            LIMITER_RELEASE_MS,
+        );
+
+        self.update_filter_bank();
+        self.update_carrier_highpass();
+        if let Some((_, top_band_high)) = self.prototype_bank.band_edges().last() {
+            self.update_air_highpass(*top_band_high);
+        }
+        // This can't be logged from `process()` since logging may allocate. `update_filter_bank()`
+        // also caps the band count there when the parameters change, and those caps are only
+        // visible through `effective_bands`, which the editor shows.
+        let effective_bands = self.prototype_bank.num_bands();
+        let requested_bands = self.band_layout().bands;
+        if effective_bands < requested_bands {
+            nih_log!(
+                "Limiting the band count to {effective_bands} instead of {requested_bands} bands \
+                 to keep the bands from getting too narrow"
+            );
+        }
+        if cfg!(any(debug_assertions, feature = "diagnostics")) {
+            self.log_configuration(num_channels);
+        }
+    }
+
+    /// Log the settings the vocoder ended up with after initializing, for debugging. This
+    /// allocates, so it must never be called from `process()`.
+    fn log_configuration(&self, num_channels: usize) {
+        let layout = &self.filter_bank_layout;
+        let band_edges = self
+            .prototype_bank
+            .band_edges()
+            .iter()
+            .map(|(low, high)| format!("{low:.1}-{high:.1}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        nih_log!(
+            "Initialized at {} Hz with {num_channels} channels and a maximum block size of {} \
+             samples, {:?} mode",
+            self.sample_rate,
+            self.max_block_size,
+            self.process_mode
+        );
+        nih_log!(
+            "{} of {} bands from {:.1} Hz to {:.1} Hz, filter order {}, {}x oversampling",
+            self.prototype_bank.num_bands(),
+            self.params.bands.value(),
+            layout.low,
+            layout.high,
+            layout.order,
+            1 << self.filter_bank_oversampling_stages
+        );
+        nih_log!("Band edges in Hz: {band_edges}");
+    }
+
+    /// Vocode the modulator in `buffer` in place and apply the output stage. `sidechain` holds the
+    /// external carrier, if there is one. Note and SysEx events are read from `next_event`, and
+    /// `song_position` is the host's playback position if its transport is playing.
+    fn process(
+        &mut self,
+        buffer: &mut [&mut [f32]],
+        sidechain: Option<&[impl AsRef<[f32]>]>,
+        song_position: Option<SongPosition>,
+        next_event: impl FnMut() -> Option<NoteEvent<BandControlMessage>>,
+    ) -> ProcessStatus {
+        // Some hosts send empty buffers to flush parameter changes. There's nothing to process
+        // then, and the smoothers, envelopes, and meters should stay exactly where they are.
+        if buffer.first().is_none_or(|channel| channel.is_empty()) {
+            return ProcessStatus::Normal;
+        }
+
+        // The host keeps compensating for the reported latency while the plugin is bypassed, so the
+        // bypassed signal is delayed by that same amount. The delay line is fed while the plugin
+        // isn't bypassed too, so bypassing doesn't start out with stale samples.
+        let bypass = self.params.bypass.value();
+        let latency = self.latency_samples() as usize;
+        for (channel_samples, channel) in buffer.iter_mut().zip(&mut self.channels) {
+            for sample in channel_samples.iter_mut() {
+                let delayed = channel.bypass_delay.process(*sample, latency);
+                if bypass {
+                    *sample = delayed;
+                }
+            }
+        }
+
+        if !bypass {
+            // An offset would otherwise read as a constant level in the lowest bands, and it would
+            // also keep the silence detection from ever kicking in
+            for (channel_samples, channel) in buffer.iter_mut().zip(&mut self.channels) {
+                for sample in channel_samples.iter_mut() {
+                    *sample = channel.input_dc_blocker.process(*sample);
+                }
+            }
+        }
+
+        let input_peak = buffer_peak(buffer);
+        self.input_peak.store(input_peak, Ordering::Relaxed);
+
+        // The main input doubles as the output, so the delayed input is already in place
+        if bypass {
+            self.output_peak.store(input_peak, Ordering::Relaxed);
+            self.publish_correlation(buffer);
+            return ProcessStatus::Normal;
+        }
+
+        // The smoothed parameter values are shared between all channels
+        let num_samples = buffer.first().map_or(0, |channel| channel.len());
+        let dry_wet = &mut self.dry_wet_scratch[..num_samples];
+        self.params
+            .dry_wet
+            .smoothed
+            .next_block(dry_wet, num_samples);
+        let engine_blend = &mut self.engine_blend_scratch[..num_samples];
+        self.params
+            .engine_blend
+            .smoothed
+            .next_block(engine_blend, num_samples);
+        let output_gain = &mut self.output_gain_scratch[..num_samples];
+        self.params
+            .output_gain
+            .smoothed
+            .next_block(output_gain, num_samples);
+        let carrier_gain = &mut self.carrier_gain_scratch[..num_samples];
+        self.params
+            .carrier_gain
+            .smoothed
+            .next_block(carrier_gain, num_samples);
+        let modulator_gain = &mut self.modulator_gain_scratch[..num_samples];
+        self.params
+            .input_trim
+            .smoothed
+            .next_block(modulator_gain, num_samples);
+        let saturation = &mut self.saturation_scratch[..num_samples];
+        self.params
+            .saturation
+            .smoothed
+            .next_block(saturation, num_samples);
+        // The filter bank follows the cutoffs once per block. Since sample accurate automation is
+        // enabled, the blocks get split wherever the host changes the cutoffs so every change
+        // still starts smoothing at the right sample.
+        self.params
+            .low_freq_cutoff
+            .smoothed
+            .next_step(num_samples as u32);
+        self.params
+            .high_freq_cutoff
+            .smoothed
+            .next_step(num_samples as u32);
+        for (trim, band_gain_params) in self.band_gain_trims.iter_mut().zip(&self.params.band_gains)
+        {
+            *trim = band_gain_params.gain.smoothed.next_step(num_samples as u32);
+        }
+        for (pan, band_pan_params) in self.band_pans.iter_mut().zip(&self.params.band_pans) {
+            *pan = band_pan_params.pan.smoothed.next_step(num_samples as u32);
+        }
+        self.stereo_spread = self
+            .params
+            .stereo_spread
+            .smoothed
+            .next_step(num_samples as u32);
+
+        // Note events always go to the synth so its voices stay in sync with the keyboard even
+        // when it's not being used as the carrier
+        let carrier_source = self.params.carrier_source.value();
+        self.process_events(
+            num_samples,
+            carrier_source == CarrierSource::InternalSynth,
+            next_event,
+        );
+
+        self.advance_lfo(num_samples, song_position);
+        self.compute_gate_sync(num_samples, song_position);
+
+        if carrier_source == CarrierSource::Robot {
+            for sample in &mut self.synth_scratch[..num_samples] {
+                *sample = self
+                    .robot_oscillator
+                    .next_sample(self.params.robot_freq.smoothed.next());
+            }
+        }
+
+        // Vocoding silence only results in silence, so once everything has been quiet for a
+        // while the band processing is skipped entirely. This is checked again for every block,
+        // so processing resumes as soon as either input picks up.
+        let carrier_peak = match carrier_source {
+            CarrierSource::Sidechain => sidechain.map_or(0.0, |sidechain| {
+                sidechain
+                    .iter()
+                    .flat_map(|channel| channel.as_ref().iter())
+                    .fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+            }),
+            CarrierSource::InternalSynth | CarrierSource::Robot => self.synth_scratch
+                [..num_samples]
+                .iter()
+                .fold(0.0, |peak: f32, sample| peak.max(sample.abs())),
+            CarrierSource::MainInput => input_peak,
+        };
+        let silent = input_peak <= TAIL_THRESHOLD
+            && carrier_peak <= TAIL_THRESHOLD
+            && self.output_peak.load(Ordering::Relaxed) <= TAIL_THRESHOLD;
+        self.silent_samples = if silent {
+            self.silent_samples.saturating_add(num_samples)
+        } else {
+            0
+        };
+        if self.silent_samples as f32 >= SILENCE_TIMEOUT_MS / 1000.0 * self.sample_rate {
+            for channel_samples in buffer.iter_mut() {
+                channel_samples.fill(0.0);
+            }
+            self.publish_band_levels(false);
+            self.output_peak.store(0.0, Ordering::Relaxed);
+            self.correlation.store(1.0, Ordering::Relaxed);
+
+            return ProcessStatus::Normal;
+        }
+
+        let vocoded = match carrier_source {
+            CarrierSource::Sidechain => {
+                // Without a carrier there's nothing to vocode, so the modulator is passed through
+                // untouched
+                match (
+                    sidechain.filter(|sidechain| !sidechain.is_empty()),
+                    self.params.input_routing.value(),
+                ) {
+                    (Some(sidechain), InputRouting::MainIsModulator) => {
+                        self.vocode(buffer, sidechain)
+                    }
+                    (Some(sidechain), InputRouting::MainIsCarrier) => {
+                        // The main buffer gets overwritten with the output, so the carrier is
+                        // copied out of it and the modulator is copied into it
+                        let mut main_input = std::mem::take(&mut self.main_input_scratch);
+                        for (channel_idx, (input_copy, channel_samples)) in
+                            main_input.iter_mut().zip(buffer.iter_mut()).enumerate()
+                        {
+                            input_copy.clear();
+                            input_copy.extend_from_slice(channel_samples);
+
+                            let modulator: &[f32] =
+                                sidechain[channel_idx % sidechain.len()].as_ref();
+                            for (sample, modulator_sample) in channel_samples
+                                .iter_mut()
+                                .zip(modulator.iter().chain(std::iter::repeat(&0.0)))
+                            {
+                                *sample = *modulator_sample;
+                            }
+                        }
+                        let carrier: &[Vec<f32>] = &main_input;
+                        let vocoded = self.vocode(buffer, carrier);
+                        self.main_input_scratch = main_input;
+                        vocoded
+                    }
+                    (None, _) => false,
+                }
+            }
+            CarrierSource::InternalSynth | CarrierSource::Robot => {
+                let synth_output = std::mem::take(&mut self.synth_scratch);
+                let vocoded = self.vocode(buffer, &[&synth_output[..num_samples]]);
+                self.synth_scratch = synth_output;
+                vocoded
+            }
+            CarrierSource::MainInput => {
+                // The main buffer gets overwritten with the output, so the carrier needs to be
+                // copied first
+                let mut main_input = std::mem::take(&mut self.main_input_scratch);
+                for (input_copy, channel_samples) in main_input.iter_mut().zip(buffer.iter()) {
+                    input_copy.clear();
+                    input_copy.extend_from_slice(channel_samples);
+                }
+                let carrier: &[Vec<f32>] = &main_input;
+                let vocoded = self.vocode(buffer, carrier);
+                self.main_input_scratch = main_input;
+                vocoded
+            }
+        };
+
+        // The editor holds on to a handle for as long as the plugin exists, so there's no telling
+        // whether anybody is looking at the band levels. Publishing them is cheap either way.
+        self.publish_band_levels(vocoded);
+
+        // A NaN or an infinity should never make it to the host. If one shows up then the filter
+        // state is poisoned as well and needs to be cleared.
+        let mut found_non_finite = false;
+        let mut output_active = false;
+        for (channel_samples, channel) in buffer.iter_mut().zip(&mut self.channels) {
+            for ((sample, gain), saturation) in channel_samples
+                .iter_mut()
+                .zip(&self.output_gain_scratch[..num_samples])
+                .zip(&self.saturation_scratch[..num_samples])
+            {
+                *sample *= gain;
+                // This fades between the clean signal and a `tanh()` soft clipper
+                *sample += (sample.tanh() - *sample) * saturation;
+                // The soft clipper turns asymmetric signals into DC. This runs before the limiter
+                // so it can't push the output past the ceiling.
+                *sample = channel.output_dc_blocker.process(*sample);
+
+                if !sample.is_finite() {
+                    *sample = 0.0;
+                    found_non_finite = true;
+                }
+                output_active |= sample.abs() > TAIL_THRESHOLD;
+            }
+        }
+        if found_non_finite {
+            self.reset_dsp_state();
+        }
+        if let [left, right, ..] = buffer {
+            if self.params.mono_safe.value() {
+                self.limit_stereo_width(left, right);
+            } else {
+                self.mono_safe_width = 1.0;
+            }
+            self.sum_to_mono(left, right);
+        }
+
+        // The limiter starts out with an empty delay line whenever it's enabled again, so it
+        // doesn't release stale samples from the last time it was used
+        let limiter = self.params.limiter.value();
+        if limiter {
+            if !self.limiter_active {
+                self.limiter.reset();
+            }
+            self.limiter.process(buffer);
+        }
+        self.limiter_active = limiter;
+
+        let output_peak = buffer_peak(buffer);
+        self.output_peak.store(output_peak, Ordering::Relaxed);
+        self.publish_correlation(buffer);
+
+        // The host may stop calling `process()` once the input goes silent, which would cut off
+        // the tail. That's especially noticeable when rendering offline. The limiter's delay line
+        // can still hold some of the tail after the rest of the output has gone silent.
+        if vocoded && (output_active || output_peak > TAIL_THRESHOLD || self.envelopes_active()) {
+            ProcessStatus::KeepAlive
+        } else {
+            ProcessStatus::Normal
+        }
+    }
+
+    /// Narrow the stereo image of `left` and `right` just enough to keep their correlation above
+    /// [`MONO_SAFE_MIN_CORRELATION`]. The width ramps down to the allowed width over the course of
+    /// the block, and it recovers over [`MONO_SAFE_RELEASE_MS`].
+    fn limit_stereo_width(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let (mid_energy, side_energy) = left.iter().zip(right.iter()).fold(
+            (0.0, 0.0),
+            |(mid_energy, side_energy): (f64, f64), (l, r)| {
+                let mid = f64::from(l + r) * 0.5;
+                let side = f64::from(l - r) * 0.5;
+                (mid_energy + mid * mid, side_energy + side * side)
+            },
+        );
+
+        // With `L = M + wS` and `R = M - wS`, `ΣLR = ΣM² - w²ΣS²` and `√(ΣL²ΣR²) <= ΣM² + w²ΣS²`.
+        // Keeping the first above the minimum correlation times the second keeps the actual
+        // correlation above the minimum as well, which gives this width.
+        let min_correlation = f64::from(MONO_SAFE_MIN_CORRELATION);
+        let max_width = if side_energy > 0.0 {
+            ((mid_energy * (1.0 - min_correlation)) / (side_energy * (1.0 + min_correlation)))
+                .sqrt()
+                .min(1.0) as f32
+        } else {
+            1.0
+        };
+
+        let num_samples = left.len();
+        let max_width_increase =
+            num_samples as f32 / (MONO_SAFE_RELEASE_MS / 1000.0 * self.sample_rate);
+        let target_width = max_width.min(self.mono_safe_width + max_width_increase);
+        let width_step = (target_width - self.mono_safe_width) / num_samples.max(1) as f32;
+        for (sample_idx, (l, r)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let width = self.mono_safe_width + width_step * (sample_idx + 1) as f32;
+            let mid = (*l + *r) * 0.5;
+            let side = (*l - *r) * 0.5 * width;
+            (*l, *r) = (mid + side, mid - side);
+        }
+        self.mono_safe_width = target_width;
+    }
+
+    /// Update `carrier_tilt_difference_db` from a block's modulator and carrier energies below and
+    /// above the center of the band range, in that order. Silence doesn't have a tilt, so the last
+    /// difference is kept while either signal is silent.
+    fn update_carrier_tilt_difference(&mut self, energies: [f64; 4], num_samples: usize) {
+        let [modulator_low, modulator_high, carrier_low, carrier_high] = energies;
+        let silence_energy = f64::from(TAIL_THRESHOLD * TAIL_THRESHOLD) * num_samples as f64;
+        if modulator_low + modulator_high <= silence_energy
+            || carrier_low + carrier_high <= silence_energy
+        {
+            return;
+        }
+
+        let balance_db = |low: f64, high: f64| {
+            (10.0 * ((high + silence_energy) / (low + silence_energy)).log10()) as f32
+        };
+        let difference_db =
+            balance_db(carrier_low, carrier_high) - balance_db(modulator_low, modulator_high);
+        let coefficient =
+            1.0 - (-(num_samples as f32) / (SPECTRAL_MATCH_MS / 1000.0 * self.sample_rate)).exp();
+        self.carrier_tilt_difference_db +=
+            coefficient * (difference_db - self.carrier_tilt_difference_db);
+    }
+
+    /// Apply the auto normalization to `modulator_gain_scratch`, which already contains the input
+    /// trim. The modulator's level is measured once per block over all of its channels, and it
+    /// keeps being tracked while the normalization is disabled so enabling it doesn't start out
+    /// with a stale level. The gain ramps towards its new value over the block.
+    fn update_modulator_gains(&mut self, modulator: &[&mut [f32]]) {
+        let num_samples = modulator.first().map_or(0, |channel| channel.len());
+        let energy: f64 = modulator
+            .iter()
+            .flat_map(|channel| channel.iter())
+            .map(|sample| f64::from(*sample) * f64::from(*sample))
+            .sum();
+        let mean_square = (energy / (num_samples * modulator.len()).max(1) as f64) as f32;
+        if mean_square > util::db_to_gain(NORMALIZE_GATE_DB).powi(2) {
+            let coefficient = 1.0
+                - (-(num_samples as f32) / (NORMALIZE_WINDOW_MS / 1000.0 * self.sample_rate)).exp();
+            self.normalize_mean_square += coefficient * (mean_square - self.normalize_mean_square);
+        }
+
+        let target_gain = if self.params.input_normalize.value() {
+            let max_gain = util::db_to_gain(NORMALIZE_MAX_GAIN_DB);
+            (util::db_to_gain(NORMALIZE_TARGET_DB) / self.normalize_mean_square.sqrt())
+                .clamp(max_gain.recip(), max_gain)
+        } else {
+            1.0
+        };
+        let gain_step = (target_gain - self.normalize_gain) / num_samples.max(1) as f32;
+        for (sample_idx, gain) in self.modulator_gain_scratch[..num_samples]
+            .iter_mut()
+            .enumerate()
+        {
+            *gain *= self.normalize_gain + gain_step * (sample_idx + 1) as f32;
+        }
+        self.normalize_gain = target_gain;
+    }
+
+    /// Fade `left` and `right` towards their mono sum, depending on the mono output parameter.
+    /// The sum is lowered by 3 dB so uncorrelated channels keep their loudness.
+    fn sum_to_mono(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let target = if self.params.mono_output.value() {
+            1.0
+        } else {
+            0.0
+        };
+        if self.mono_output_amount == 0.0 && target == 0.0 {
+            return;
+        }
+
+        let fade_step = 1.0 / (MONO_OUTPUT_FADE_MS / 1000.0 * self.sample_rate);
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            self.mono_output_amount +=
+                (target - self.mono_output_amount).clamp(-fade_step, fade_step);
+
+            let mono = (*l + *r) * FRAC_1_SQRT_2 as f32;
+            *l += (mono - *l) * self.mono_output_amount;
+            *r += (mono - *r) * self.mono_output_amount;
+        }
+    }
+
+    /// Store the correlation between the first two channels in `buffer`.
+    fn publish_correlation(&self, buffer: &[&mut [f32]]) {
+        let correlation = match buffer {
+            [left, right, ..] => {
+                let (cross_energy, left_energy, right_energy) = left.iter().zip(right.iter()).fold(
+                    (0.0, 0.0, 0.0),
+                    |(cross_energy, left_energy, right_energy): (f64, f64, f64), (l, r)| {
+                        let (l, r) = (f64::from(*l), f64::from(*r));
+                        (
+                            cross_energy + l * r,
+                            left_energy + l * l,
+                            right_energy + r * r,
+                        )
+                    },
+                );
+
+                // A single silent channel is uncorrelated with the other channel, while two
+                // silent channels are considered identical
+                if left_energy > 0.0 && right_energy > 0.0 {
+                    (cross_energy / (left_energy * right_energy).sqrt()).clamp(-1.0, 1.0) as f32
+                } else if left_energy > 0.0 || right_energy > 0.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            _ => 1.0,
+        };
+
+        self.correlation.store(correlation, Ordering::Relaxed);
+    }
+
+    /// The band layout described by the current parameter values. This uses the cutoff
+    /// parameters' current smoothed values.
+    fn band_layout(&self) -> BandLayout {
+        let (max_bands, max_order) = if self.eco_active {
+            (ECO_MAX_BANDS, ECO_MAX_FILTER_ORDER)
+        } else {
+            (MAX_BANDS, MAX_FILTER_ORDER)
+        };
+        let order = if self.process_mode == ProcessMode::Offline {
+            self.params.filter_order.value() as usize + OFFLINE_EXTRA_FILTER_ORDER
+        } else {
+            self.params.filter_order.value() as usize
+        };
+        let bandwidth = if self.params.analog_emulation.value() {
+            ANALOG_BAND_OVERLAP
+        } else {
+            1.0
+        } / self.params.band_q.value();
+
+        BandLayout {
+            low: self.params.low_freq_cutoff.smoothed.previous_value(),
+            high: self.params.high_freq_cutoff.smoothed.previous_value(),
+            bands: (self.params.bands.value() as usize).min(max_bands),
+            order: order.min(max_order),
+            spacing: self.params.spacing.value(),
+            // The GUI may be holding a write lock, in which case the old value is kept for now
+            spacing_curve: self
+                .params
+                .settings
+                .try_read()
+                .map_or(self.filter_bank_layout.spacing_curve, |settings| {
+                    settings.band_spacing_curve.clamp(0.25, 4.0)
+                }),
+            bandwidth: bandwidth / self.params.analysis_q.value(),
+            synthesis_bandwidth: bandwidth / self.params.synthesis_q.value(),
+        }
+    }
+
+    /// The number of 2x oversampling stages to use. This is raised when rendering offline.
+    fn oversampling_stages(&self) -> usize {
+        let stages = self.params.oversampling.value().stages();
+        if self.process_mode == ProcessMode::Offline {
+            stages.max(OFFLINE_MIN_OVERSAMPLING_STAGES)
+        } else {
+            stages
+        }
+    }
+
+    /// The engines the current settings need. The FFT engine keeps running until the engine
+    /// blend has faded all the way out.
+    fn active_engines(&self) -> ActiveEngines {
+        let engine_blend = &self.params.engine_blend;
+        match self.params.engine.value() {
+            Engine::FilterBank => ActiveEngines {
+                filter_bank: true,
+                fft: engine_blend.value() > 0.0 || engine_blend.smoothed.previous_value() > 0.0,
+            },
+            Engine::Fft => ActiveEngines {
+                filter_bank: false,
+                fft: true,
+            },
+        }
+    }
+
+    /// The latency `engines` add on top of the lookahead, in samples. The FFT engine's latency is
+    /// always longer than the oversampling filters' latency, so while blending the filter bank is
+    /// delayed to match the FFT engine.
+    fn engine_latency_samples(&self, engines: ActiveEngines) -> u32 {
+        if engines.fft {
+            spectral::LATENCY_SAMPLES
+        } else {
+            oversampling::latency_samples(self.oversampling_stages())
+        }
+    }
+
+    /// Whether the envelopes should be frozen, either because of the freeze parameter or because
+    /// of a SysEx override.
+    fn freeze(&self) -> bool {
+        self.sysex_freeze
+            .unwrap_or_else(|| self.params.freeze.value())
+    }
+
+    /// Whether eco mode should be active. There's no need to save CPU when rendering offline.
+    fn eco_mode(&self) -> bool {
+        self.params.eco_mode.value() && self.process_mode != ProcessMode::Offline
+    }
+
+    /// Rebuild the band filters for the current sample rate and band layout. The filters are
+    /// designed for the oversampled sample rate. All of the storage is allocated up front for
+    /// [`MAX_BANDS`] bands, so this is safe to call from the audio thread.
+    fn update_filter_bank(&mut self) {
+        let layout = self.band_layout();
+        let oversampling_stages = self.oversampling_stages();
+        let processing_sample_rate = self.sample_rate * (1 << oversampling_stages) as f32;
+
+        // The bilinear transform can't handle frequencies at or above the Nyquist frequency, so
+        // the band range is kept a bit below that. Bands that still can't be built are skipped.
+        // Anything above the original Nyquist frequency would be removed when downsampling, so
+        // oversampling doesn't extend this range.
+        let max_frequency = self.sample_rate * MAX_FREQUENCY_FRACTION;
+        let previous_num_bands = self.prototype_bank.num_bands();
+        let mut clamped_layout = BandLayout {
+            low: layout.low.min(layout.high).min(max_frequency),
+            high: layout.high.max(layout.low).min(max_frequency),
+            ..layout
+        };
+        let min_band_width = self.params.min_band_width.value();
+        // Automation can move the cutoffs past each other, in which case they're simply swapped.
+        // When they're too close together for even a single band, that band gets the minimum
+        // width and is centered between them.
+        let center_frequency = (clamped_layout.low + clamped_layout.high) / 2.0;
+        let single_band_width = MIN_BAND_WIDTH_HZ.max(min_band_width * center_frequency);
+        if clamped_layout.high - clamped_layout.low < single_band_width {
+            let low = (center_frequency - single_band_width / 2.0).max(MIN_BAND_FREQUENCY);
+            clamped_layout.high = (low + single_band_width).min(max_frequency);
+            clamped_layout.low = clamped_layout.high - single_band_width;
+            clamped_layout.bands = clamped_layout.bands.min(1);
+        } else {
+            clamped_layout.bands = clamped_layout.max_bands(MIN_BAND_WIDTH_HZ, min_band_width);
+        }
+        self.prototype_bank
+            .rebuild(processing_sample_rate, &clamped_layout);
+        self.carrier_prototype_bank.rebuild(
+            processing_sample_rate,
+            &BandLayout {
+                bandwidth: clamped_layout.synthesis_bandwidth,
+                ..clamped_layout
+            },
+        );
+        // Bands that can't be built are skipped, which depends on the filters' widths. Both banks
+        // need to have the same bands, so in the rare case where they don't the carrier uses the
+        // analysis filters instead.
+        if self.carrier_prototype_bank.band_edges() != self.prototype_bank.band_edges() {
+            self.carrier_prototype_bank.copy_from(&self.prototype_bank);
+        }
+        self.effective_bands
+            .store(self.prototype_bank.num_bands(), Ordering::Relaxed);
+
+        // While the cutoffs are being smoothed the filters follow them in small steps, which are
+        // retuned in place without clicking. The old filter state is meaningless at a different
+        // sample rate though.
+        let num_bands = self.prototype_bank.num_bands();
+        let rate_changed = oversampling_stages != self.filter_bank_oversampling_stages;
+        let cutoff_step = !rate_changed
+            && min_band_width == self.filter_bank_min_band_width
+            && !self
+                .filter_bank_layout
+                .differs_from(&layout, CUTOFF_REBUILD_THRESHOLD);
+
+        // Any other change would click, so the old carrier filters are kept running with their
+        // state to crossfade them with the new ones. Their weights are based on the old band
+        // layout's formant map and band gains, so they're captured before those are updated.
+        if !cutoff_step {
+            let shared_analysis = self.params.modulator_mono.value() && self.channels.len() > 1;
+            let crossfade_length =
+                (FILTER_BANK_CROSSFADE_MS / 1000.0 * processing_sample_rate) as usize;
+            for channel_idx in 0..self.channels.len() {
+                let analysis_idx = if shared_analysis { 0 } else { channel_idx };
+                for (band_idx, source_band_idx) in self.formant_map.iter().enumerate() {
+                    let weight = self.channels[analysis_idx].band_amplitudes[*source_band_idx]
+                        * self.band_gains[channel_idx % 2][band_idx];
+                    self.channels[channel_idx].previous_band_weights[band_idx] = weight;
+                }
+
+                // If a crossfade is still running, the filters it was fading in become the old
+                // filters and the ones it was fading out are dropped
+                let channel = &mut self.channels[channel_idx];
+                channel
+                    .previous_carrier_bank
+                    .clone_from(&channel.carrier_bank);
+                channel.crossfade_remaining = crossfade_length;
+                channel.crossfade_length = crossfade_length;
+            }
+        }
+
+        for channel in &mut self.channels {
+            for (channel_bank, prototype_bank) in [
+                (&mut channel.modulator_bank, &self.prototype_bank),
+                (&mut channel.carrier_bank, &self.carrier_prototype_bank),
+            ] {
+                channel_bank.copy_from(prototype_bank);
+                if rate_changed {
+                    channel_bank.reset();
+                }
+            }
+
+            // Newly added bands start out silent instead of picking up stale envelopes from
+            // before the band count was lowered
+            let first_new_band = previous_num_bands.min(num_bands);
+            channel.envelopes[first_new_band..].fill(0.0);
+            channel.mean_squares[first_new_band..].fill(0.0);
+            channel.gates_open[first_new_band..].fill(false);
+            channel.band_amplitudes[first_new_band..].fill(0.0);
+            channel.spectral_levels[first_new_band..].fill(0.0);
+        }
+
+        self.filter_bank_layout = layout;
+        self.filter_bank_oversampling_stages = oversampling_stages;
+        self.filter_bank_min_band_width = min_band_width;
+        self.update_formant_map(self.formant_map_shift);
+    }
+
+    /// Retune the channels' carrier highpass filters to the carrier highpass parameter's current
+    /// value. The filters keep their state, so this can be done while the cutoff is automated.
+    fn update_carrier_highpass(&mut self) {
+        let frequency = self.params.carrier_hp.value();
+        let highpass = Biquad::highpass(
+            self.sample_rate,
+            frequency.min(self.sample_rate * 0.4),
+            FRAC_1_SQRT_2,
+        );
+        for channel in &mut self.channels {
+            channel.carrier_highpass.copy_coefficients(&highpass);
+        }
+
+        self.carrier_highpass_frequency = frequency;
+    }
+
+    /// Retune the channels' air band highpass filters to `frequency`, the top band's upper edge.
+    /// Like the carrier highpass filters these keep their state.
+    fn update_air_highpass(&mut self, frequency: f32) {
+        let highpass = Biquad::highpass(
+            self.sample_rate,
+            frequency.min(self.sample_rate * 0.4),
+            FRAC_1_SQRT_2,
+        );
+        for channel in &mut self.channels {
+            channel.modulator_air_highpass.copy_coefficients(&highpass);
+            channel.carrier_air_highpass.copy_coefficients(&highpass);
+        }
+
+        self.air_frequency = frequency;
+    }
+
+    /// Recompute which modulator band shapes which carrier band for a formant shift of
+    /// `formant_shift` semitones. Every carrier band takes the envelope of the modulator band
+    /// containing the carrier band's shifted down center frequency, so positive shifts move the
+    /// formants up. Shifts past the ends of the range are clamped to the outermost bands.
+    fn update_formant_map(&mut self, formant_shift: f32) {
+        let ratio = 2.0f32.powf(formant_shift / 12.0);
+        let band_edges = self.prototype_bank.band_edges();
+        let num_bands = band_edges.len();
+
+        self.formant_map.clear();
+        self.formant_map
+            .extend(band_edges.iter().map(|(band_low, band_high)| {
+                let source_frequency = (band_low * band_high).sqrt() / ratio;
+                band_edges
+                    .partition_point(|(_, source_high)| *source_high <= source_frequency)
+                    .min(num_bands - 1)
+            }));
+
+        self.formant_map_shift = formant_shift;
+    }
+
+    /// Compute the formant shift LFO's value for this block and advance it by `num_samples`
+    /// samples. While the host's transport is playing the LFO follows the song position, so it
+    /// lines up the same way on every playback. Otherwise it runs freely.
+    fn advance_lfo(&mut self, num_samples: usize, song_position: Option<SongPosition>) {
+        let rate = self.params.lfo_rate.value();
+        if let Some(song_position) = song_position {
+            let pos_seconds = song_position.beats * 60.0 / song_position.tempo;
+            self.lfo_phase = (pos_seconds * f64::from(rate)).rem_euclid(1.0) as f32;
+        }
+
+        self.lfo_value = (self.lfo_phase * TAU).sin();
+        self.lfo_phase = (self.lfo_phase + rate * num_samples as f32 / self.sample_rate).fract();
+    }
+
+    /// Compute the tempo synced gate's gain for every sample in this block and store it in
+    /// `gate_sync_scratch`. The gate is open for the first half of every note division. Without a
+    /// playing transport there's no tempo to sync to, so the gate stays open just like when it's
+    /// turned off.
+    fn compute_gate_sync(&mut self, num_samples: usize, song_position: Option<SongPosition>) {
+        let fade_step = 1.0 / (GATE_SYNC_FADE_MS / 1000.0 * self.sample_rate);
+        // The host compensates for the latency by moving the output back in time, so the gate
+        // follows the position the output ends up at to stay on the grid
+        let grid = match (self.params.gate_sync.value().beats(), song_position) {
+            (Some(division), Some(song_position)) => {
+                let beats_per_sample = song_position.tempo / 60.0 / f64::from(self.sample_rate);
+                let start_beats =
+                    song_position.beats - f64::from(self.latency_samples()) * beats_per_sample;
+
+                Some((start_beats, beats_per_sample, division))
+            }
+            _ => None,
+        };
+
+        for (sample_idx, gain) in self.gate_sync_scratch[..num_samples].iter_mut().enumerate() {
+            let target = match grid {
+                Some((start_beats, beats_per_sample, division)) => {
+                    let beats = start_beats + beats_per_sample * sample_idx as f64;
+                    if (beats / division).rem_euclid(1.0) < 0.5 {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                None => 1.0,
+            };
+
+            self.gate_sync_gain += (target - self.gate_sync_gain).clamp(-fade_step, fade_step);
+            *gain = self.gate_sync_gain;
+        }
+    }
+
+    /// Spread the band gain trims out over the active bands. Bands that fall between two trims are
+    /// interpolated in decibels, and the tilt is applied on top of that as a ramp in decibels
+    /// across the bands. With more than one channel, every band is panned to its interpolated band
+    /// pan position, and alternating bands are moved further to the left and to the right by the
+    /// stereo spread amount. While a band is soloed all other bands are muted. With automatic gain
+    /// compensation the gains are scaled according to the band count, and any band gains set over
+    /// SysEx are applied last. This only sets the targets the band gains ramp towards.
+    fn update_band_gains(&mut self) {
+        let num_bands = self.prototype_bank.num_bands();
+        let solo_band = usize::try_from(self.params.solo_band.value())
+            .ok()
+            .filter(|solo_band| *solo_band < num_bands);
+        let trims = &self.band_gain_trims;
+        let pans = &self.band_pans;
+        let tilt_db = self.params.tilt.value() * TILT_RANGE_DB
+            - (self.params.spectral_match.value() * self.carrier_tilt_difference_db)
+                .clamp(-SPECTRAL_MATCH_MAX_DB, SPECTRAL_MATCH_MAX_DB);
+        let stereo = self.channels.len() > 1;
+        // Every band's share of the carrier's energy and of the modulator's level both shrink as
+        // the bands get narrower, so for noisy signals the summed output drops off by the square
+        // root of the band count
+        let auto_gain = if self.params.auto_gain.value() {
+            (num_bands as f32 / AUTO_GAIN_REFERENCE_BANDS).sqrt()
+        } else {
+            1.0
+        };
+        // The editor may be holding a write lock, in which case the gains from the last block are
+        // kept for now
+        if let Ok(settings) = self.params.settings.try_read() {
+            for (curve_gain, (low, high)) in self
+                .band_curve_gains
+                .iter_mut()
+                .zip(self.prototype_bank.band_edges())
+            {
+                *curve_gain = util::db_to_gain(curve::gain_db_at(
+                    &settings.band_gain_curve,
+                    (low * high).sqrt(),
+                ));
+            }
+        }
+
+        let [left_gains, right_gains] = &mut self.band_gain_targets;
+        for (band_idx, (left_gain, right_gain)) in left_gains[..num_bands]
+            .iter_mut()
+            .zip(&mut right_gains[..num_bands])
+            .enumerate()
+        {
+            let gain = if solo_band.is_some_and(|solo_band| solo_band != band_idx) {
+                0.0
+            } else {
+                // The tilt pivots around the center of the band range
+                let tilt = if num_bands > 1 {
+                    util::db_to_gain(
+                        tilt_db * (band_idx as f32 / (num_bands - 1) as f32 * 2.0 - 1.0),
+                    )
+                } else {
+                    1.0
+                };
+
+                let (lower, upper, t) = control_points(band_idx, num_bands, NUM_BAND_GAINS);
+                trims[lower]
+                    * (trims[upper] / trims[lower]).powf(t)
+                    * tilt
+                    * auto_gain
+                    * self.sysex_band_gains[band_idx]
+                    * self.band_curve_gains[band_idx]
+            };
+
+            // This is a constant power pan law, so the band's total energy stays the same no
+            // matter where it's panned. Both sides are at unity gain when the band is centered.
+            let pan = if stereo {
+                let (lower, upper, t) = control_points(band_idx, num_bands, NUM_BAND_PANS);
+                let spread = if band_idx % 2 == 0 {
+                    -self.stereo_spread
+                } else {
+                    self.stereo_spread
+                };
+
+                (pans[lower] + (pans[upper] - pans[lower]) * t + spread).clamp(-1.0, 1.0)
+            } else {
+                0.0
+            };
+            let angle = (pan + 1.0) * FRAC_PI_4;
+            *left_gain = gain * angle.cos() * SQRT_2;
+            *right_gain = gain * angle.sin() * SQRT_2;
+        }
+    }
+
+    /// Compute the per-sample steps that take the band gains to their targets in `num_samples`
+    /// samples.
+    fn start_band_gain_ramp(&mut self, num_samples: usize) {
+        for ((gains, targets), steps) in self
+            .band_gains
+            .iter()
+            .zip(&self.band_gain_targets)
+            .zip(&mut self.band_gain_steps)
+        {
+            for ((gain, target), step) in gains.iter().zip(targets).zip(steps.iter_mut()) {
+                *step = (target - gain) / num_samples as f32;
+            }
+        }
+    }
+
+    /// Move the band gains to their targets immediately, skipping the rest of the ramp.
+    fn snap_band_gains(&mut self) {
+        for (gains, targets) in self.band_gains.iter_mut().zip(&self.band_gain_targets) {
+            gains.copy_from_slice(targets);
+        }
+    }
+
+    /// Clear the filter and envelope follower state, as if the plugin has only been fed silence.
+    fn reset_dsp_state(&mut self) {
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+    }
+
+    /// Whether any channel's band envelopes are still above [`TAIL_THRESHOLD`].
+    fn envelopes_active(&self) -> bool {
+        let num_bands = self.prototype_bank.num_bands();
+        self.channels.iter().any(|channel| {
+            channel.band_amplitudes[..num_bands]
+                .iter()
+                .any(|amplitude| *amplitude > TAIL_THRESHOLD)
+        })
+    }
+
+    /// Publish every band's envelope level for the editor, using the loudest channel's envelope
+    /// for each band. If nothing was vocoded then all bands are silent.
+    fn publish_band_levels(&self, vocoded: bool) {
+        let active_bands = if vocoded {
+            self.channels
+                .first()
+                .map_or(0, |channel| channel.modulator_bank.num_bands())
+        } else {
+            0
+        };
+
+        for (band_idx, level) in self.band_levels.iter().enumerate() {
+            let envelope = if band_idx < active_bands {
+                self.channels
+                    .iter()
+                    .map(|channel| channel.band_amplitudes[band_idx])
+                    .fold(0.0, f32::max)
+            } else {
+                0.0
+            };
+
+            level.store(envelope, Ordering::Relaxed);
+        }
+    }
+
+    /// Handle this block's note and SysEx events, read from `next_event`, sample accurately. If
+    /// `render` is set, the synth's output is written to `synth_scratch`.
+    fn process_events(
+        &mut self,
+        num_samples: usize,
+        render: bool,
+        mut next_event: impl FnMut() -> Option<NoteEvent<BandControlMessage>>,
+    ) {
+        self.synth.set_envelope(
+            self.params.synth_attack_ms.value(),
+            self.params.synth_release_ms.value(),
+        );
+        self.synth
+            .set_max_voices(self.params.max_voices.value() as usize);
+
+        let mut pending_event = next_event();
+        for sample_idx in 0..num_samples {
+            while let Some(event) =
+                pending_event.take_if(|event| event.timing() <= sample_idx as u32)
+            {
+                match event {
+                    NoteEvent::NoteOn { note, velocity, .. } => self.synth.note_on(note, velocity),
+                    NoteEvent::NoteOff { note, .. } => self.synth.note_off(note),
+                    NoteEvent::MidiSysEx { message, .. } => self.handle_band_control(message),
+                    _ => (),
+                }
+
+                pending_event = next_event();
+            }
+
+            self.synth_scratch[sample_idx] = if render {
+                self.synth.next_sample()
+            } else {
+                0.0
+            };
+        }
+    }
+
+    /// Apply a band control SysEx message. The band gains only take effect at the start of the
+    /// next block, where they're ramped to like the band gain parameters.
+    fn handle_band_control(&mut self, message: BandControlMessage) {
+        match message {
+            BandControlMessage::BandGains {
+                first_band,
+                num_bands,
+                gains,
+            } => {
+                for (band_gain, value) in self.sysex_band_gains[first_band..first_band + num_bands]
+                    .iter_mut()
+                    .zip(gains)
+                {
+                    *band_gain = BandControlMessage::gain_from_value(value);
+                }
+            }
+            BandControlMessage::Freeze(freeze) => self.sysex_freeze = freeze,
+            BandControlMessage::ClearOverrides => {
+                self.sysex_band_gains.fill(1.0);
+                self.sysex_freeze = None;
+            }
         }
     }
-}
 
-impl Default for StlVocoderParams {
-    fn default() -> Self {
-        Self {
-            low_freq_cutoff: FloatParam::new(
-                "Formant lower end",
-                300f32,
-                FloatRange::Linear {
-                    min: (0f32),
-                    max: (20000_f32),
-                },
-            ),
-            high_freq_cutoff: FloatParam::new(
-                "Formant upper end",
-                3400f32,
-                FloatRange::Linear {
-                    min: (0f32),
-                    max: (20000_f32),
+    /// Vocode the main input channels in place, using `carrier` as the carrier signal. The result
+    /// is mixed with the dry signal according to the precomputed dry/wet values. Returns `false`
+    /// and leaves the input untouched if the current settings don't result in any usable bands.
+    fn vocode(&mut self, channels: &mut [&mut [f32]], carrier: &[impl AsRef<[f32]>]) -> bool {
+        // Switching eco mode changes the band count, which would click. The wet signal is faded
+        // out first, and the filter bank is only switched over once it's silent.
+        let eco_mode = self.eco_mode();
+        if eco_mode != self.eco_active && self.eco_switch_gain <= 0.0 {
+            self.eco_active = eco_mode;
+        }
+        let eco_switch_step = if eco_mode == self.eco_active {
+            1.0
+        } else {
+            -1.0
+        } / (ECO_FADE_MS / 1000.0 * self.sample_rate);
+
+        // The filter bank only needs to be rebuilt when one of the layout parameters changes.
+        // Recomputing the filters for every step of the cutoff smoothing would be too expensive,
+        // so while the cutoffs are still moving the filters only follow in small increments. Once
+        // they have settled the filters are brought in line with the final values.
+        let cutoffs_smoothing = self.params.low_freq_cutoff.smoothed.is_smoothing()
+            || self.params.high_freq_cutoff.smoothed.is_smoothing();
+        let cutoff_tolerance = if cutoffs_smoothing {
+            CUTOFF_REBUILD_THRESHOLD
+        } else {
+            0.0
+        };
+        if self
+            .filter_bank_layout
+            .differs_from(&self.band_layout(), cutoff_tolerance)
+            || self.filter_bank_oversampling_stages != self.oversampling_stages()
+            || self.filter_bank_min_band_width != self.params.min_band_width.value()
+        {
+            self.update_filter_bank();
+        }
+        if self
+            .channels
+            .first()
+            .is_none_or(|channel| channel.modulator_bank.num_bands() == 0)
+        {
+            return false;
+        }
+
+        // Switching engines or starting and stopping the blend changes the latency, so the output
+        // jumps no matter what. Clearing the state at least keeps an engine's state from the last
+        // time it was used from leaking through.
+        let engines = self.active_engines();
+        if engines != self.engines {
+            self.reset_dsp_state();
+            self.engines = engines;
+        }
+
+        if self.params.carrier_hp.value() != self.carrier_highpass_frequency {
+            self.update_carrier_highpass();
+        }
+        let air_frequency = self
+            .prototype_bank
+            .band_edges()
+            .last()
+            .map_or(self.air_frequency, |(_, high)| *high);
+        if air_frequency != self.air_frequency {
+            self.update_air_highpass(air_frequency);
+        }
+
+        let formant_shift =
+            self.params.formant_shift.value() + self.params.lfo_depth.value() * self.lfo_value;
+        if formant_shift != self.formant_map_shift {
+            self.update_formant_map(formant_shift);
+        }
+        self.update_band_gains();
+
+        // The band processing runs at the oversampled rate, the sibilance detection does not. The
+        // FFT engine is never oversampled.
+        let oversampling_stages = if engines.filter_bank {
+            self.filter_bank_oversampling_stages
+        } else {
+            0
+        };
+        let processing_sample_rate = self.sample_rate * (1 << oversampling_stages) as f32;
+        let carrier_delay = lookahead_samples(self.params.lookahead_ms.value(), self.sample_rate);
+        let engine_delay = self.engine_latency_samples(engines) as usize;
+        let dry_delay = carrier_delay + engine_delay;
+        let filter_bank_delay =
+            engine_delay - oversampling::latency_samples(oversampling_stages) as usize;
+        let gate_threshold = self.params.gate_threshold.value();
+        let (gate_open_level, gate_close_level) = if gate_threshold > GATE_THRESHOLD_OFF_DB {
+            (
+                util::db_to_gain(gate_threshold + GATE_HYSTERESIS_DB),
+                util::db_to_gain(gate_threshold),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        let envelope_shape = self.params.envelope_shape.value();
+        let envelope_rate = |time_ms: f32| match envelope_shape {
+            EnvelopeShape::Exponential => one_pole_coefficient(time_ms, processing_sample_rate),
+            EnvelopeShape::Linear => 1.0 / (time_ms / 1000.0 * processing_sample_rate),
+        };
+
+        // The times are scaled relative to the band range's geometric center, so the middle band
+        // keeps the global attack and release times
+        let attack_ms = self.params.attack_ms.value();
+        let release_ms = self.params.release_ms.value();
+        let env_tilt = self.params.env_tilt.value();
+        let band_edges = self.prototype_bank.band_edges();
+        let center_frequency = match (band_edges.first(), band_edges.last()) {
+            (Some((low, _)), Some((_, high))) => (low * high).sqrt(),
+            _ => 1.0,
+        };
+        for ((attack_rate, release_rate), (low, high)) in self
+            .band_attack_rates
+            .iter_mut()
+            .zip(self.band_release_rates.iter_mut())
+            .zip(band_edges)
+        {
+            let time_scale = ((low * high).sqrt() / center_frequency).powf(-env_tilt);
+            *attack_rate = envelope_rate(attack_ms * time_scale);
+            *release_rate = envelope_rate(release_ms * time_scale);
+        }
+        let band_hold_samples =
+            (self.params.band_hold_ms.value() / 1000.0 * processing_sample_rate).round() as u32;
+        let band_decay_samples =
+            self.params.band_decay_ms.value() / 1000.0 * processing_sample_rate;
+        let band_decay_rate = if band_decay_samples >= 1.0 {
+            1.0 - 0.001f32.powf(band_decay_samples.recip())
+        } else {
+            1.0
+        };
+        let sibilance_coefficient = one_pole_coefficient(SIBILANCE_DETECTOR_MS, self.sample_rate);
+        // When the input vocodes itself the carrier already contains the modulator's consonants,
+        // so adding noise on top of that would emphasize them twice
+        let self_vocoding = self.params.carrier_source.value() == CarrierSource::MainInput;
+        let sibilance_amount = if self_vocoding {
+            0.0
+        } else {
+            self.params.sibilance_amount.value()
+        };
+        let transient_amount = self.params.transient_amount.value();
+        let pre_emphasis_coefficient =
+            self.params.pre_emphasis.value() * MAX_PRE_EMPHASIS_COEFFICIENT;
+        let synthesis_settings = SynthesisSettings {
+            self_vocoding,
+            analog_emulation: self.params.analog_emulation.value(),
+            phase_align: self.params.phase_align.value(),
+        };
+        let air_amount = self.params.air_amount.value();
+        let air_attack = one_pole_coefficient(self.params.attack_ms.value(), self.sample_rate);
+        let air_release = one_pole_coefficient(self.params.release_ms.value(), self.sample_rate);
+        let duck_depth = self.params.duck_depth.value();
+        let duck_attack = one_pole_coefficient(DUCK_ATTACK_MS, self.sample_rate);
+        let duck_release = one_pole_coefficient(DUCK_RELEASE_MS, self.sample_rate);
+        let freeze_target = if self.freeze() { 1.0 } else { 0.0 };
+        let freeze_step = 1.0 / (FREEZE_FADE_MS / 1000.0 * processing_sample_rate);
+
+        // When the modulator is summed to mono only the first channel's envelope followers are
+        // used, and its envelopes shape every channel's carrier
+        let modulator_mono = self.params.modulator_mono.value() && channels.len() > 1;
+        let env_link = self.params.env_link.value() && channels.len() > 1;
+        if modulator_mono {
+            let num_samples = channels[0].len();
+            let mono_modulator = &mut self.mono_modulator_scratch[..num_samples];
+            mono_modulator.fill(0.0);
+            for channel in channels.iter() {
+                for (mono_sample, sample) in mono_modulator.iter_mut().zip(channel.iter()) {
+                    *mono_sample += sample / channels.len() as f32;
+                }
+            }
+        }
+
+        self.update_modulator_gains(channels);
+
+        let dry_crossover = self.params.dry_crossover.value();
+        let crossover_coefficient = (dry_crossover > DRY_CROSSOVER_OFF_HZ)
+            .then(|| 1.0 - (-TAU * dry_crossover / self.sample_rate).exp());
+        let carrier_bass_bypass = self.params.carrier_bass_bypass.value();
+        let bass_bypass_coefficient = (carrier_bass_bypass > CARRIER_BASS_BYPASS_OFF_HZ)
+            .then(|| 1.0 - (-TAU * carrier_bass_bypass / self.sample_rate).exp());
+
+        // Mid/side processing needs a stereo carrier and output channel pairs
+        let ms_mode = self.params.ms_mode.value() && channels.len() >= 2 && carrier.len() >= 2;
+
+        // The FFT engine only updates the band levels once per frame, so on its own the band gains
+        // and the freeze amount only change once per block as well. While blending, it uses the
+        // values from the start of the filter bank's ramps.
+        let num_samples = channels.first().map_or(0, |channel| channel.len());
+        if !engines.filter_bank {
+            self.snap_band_gains();
+            let max_freeze_change = freeze_step * num_samples as f32;
+            self.freeze_amount +=
+                (freeze_target - self.freeze_amount).clamp(-max_freeze_change, max_freeze_change);
+        }
+        let fft_window = self.params.fft_window.value();
+        let fft_overlap = self.params.fft_overlap.value();
+        let frame_rate = self.sample_rate / fft_overlap.hop_size() as f32;
+        let spectral_settings = SpectralSettings {
+            sample_rate: self.sample_rate,
+            band_edges: self.prototype_bank.band_edges(),
+            formant_map: &self.formant_map,
+            band_gains: &self.band_gains[0],
+            attack: one_pole_coefficient(self.params.attack_ms.value(), frame_rate)
+                * (1.0 - self.freeze_amount),
+            release: one_pole_coefficient(self.params.release_ms.value(), frame_rate)
+                * (1.0 - self.freeze_amount),
+            self_vocoding,
+        };
+
+        let tilt_split_coefficient = 1.0 - (-TAU * center_frequency / self.sample_rate).exp();
+        let mut tilt_energies = [0.0f64; 4];
+
+        let mut num_oversampled_samples = 0;
+        for (channel_idx, (modulator, channel)) in
+            channels.iter().zip(self.channels.iter_mut()).enumerate()
+        {
+            let num_samples = modulator.len();
+            num_oversampled_samples = num_samples << oversampling_stages;
+            let modulator: &[f32] = if modulator_mono {
+                &self.mono_modulator_scratch[..num_samples]
+            } else {
+                modulator
+            };
+
+            // A mono sidechain feeds every output channel. Every channel of a stereo sidechain is
+            // vocoded into the matching output channel, alternating between the left and right
+            // channels when there are more than two main channels. In mid/side mode the even
+            // channel of every pair vocodes the carrier's mid signal and the odd channel vocodes
+            // its side signal.
+            //
+            // The carrier should always be exactly as long as the modulator. If it's ever shorter
+            // the missing samples are treated as silence, so the band processing still gets one
+            // carrier sample for every modulator sample and the buffer lengths below line up.
+            let carrier_channel = |carrier_channel_idx: usize| {
+                let samples: &[f32] = carrier[carrier_channel_idx % carrier.len()].as_ref();
+                nih_debug_assert_eq!(samples.len(), num_samples);
+                samples.iter().copied().chain(std::iter::repeat(0.0))
+            };
+            let (carrier_left, carrier_right) = if ms_mode {
+                (
+                    carrier_channel(channel_idx & !1),
+                    carrier_channel((channel_idx & !1) + 1),
+                )
+            } else {
+                (carrier_channel(channel_idx), carrier_channel(channel_idx))
+            };
+            let side_sign = if channel_idx % 2 == 0 { 1.0 } else { -1.0 };
+
+            // A tonal carrier has hardly any high frequency content, so consonants get lost
+            // unless some noise is mixed in while the modulator is sibilant. The noise and the
+            // transient boost are applied after the lookahead delay since they follow the
+            // modulator, not the carrier.
+            self.carrier_scratch.clear();
+            self.carrier_scratch.extend(
+                modulator
+                    .iter()
+                    .zip(carrier_left)
+                    .zip(carrier_right)
+                    .zip(&self.carrier_gain_scratch)
+                    .zip(channel.carrier_bass.iter_mut())
+                    .map(|((((m, l), r), carrier_gain), bass)| {
+                        let sibilance = channel
+                            .sibilance_detector
+                            .process(*m, sibilance_coefficient);
+                        let transient = channel.transient_detector.process(*m);
+                        let c = if ms_mode {
+                            (l + side_sign * r) * 0.5
+                        } else {
+                            l
+                        };
+                        let c = match bass_bypass_coefficient {
+                            Some(coefficient) => {
+                                channel.carrier_split_state = flush_denormal(
+                                    channel.carrier_split_state
+                                        + coefficient * (c - channel.carrier_split_state),
+                                );
+                                *bass = if ms_mode {
+                                    let own = if side_sign > 0.0 { l } else { r };
+                                    channel.carrier_bypass_state = flush_denormal(
+                                        channel.carrier_bypass_state
+                                            + coefficient * (own - channel.carrier_bypass_state),
+                                    );
+                                    channel.carrier_bypass_state
+                                } else {
+                                    channel.carrier_split_state
+                                };
+
+                                c - channel.carrier_split_state
+                            }
+                            None => {
+                                *bass = 0.0;
+                                c
+                            }
+                        };
+                        let c = channel
+                            .carrier_highpass
+                            .process(f64::from(c * carrier_gain))
+                            as f32;
+                        (channel.carrier_delay.process(c, carrier_delay)
+                            + channel.noise.next_sample() * sibilance * sibilance_amount)
+                            * (1.0 + transient * transient_amount)
+                    }),
+            );
+
+            // This first order pre-emphasis filter only affects the signal that's analyzed
+            self.modulator_scratch.clear();
+            self.modulator_scratch
+                .extend(
+                    modulator
+                        .iter()
+                        .zip(&self.modulator_gain_scratch)
+                        .map(|(m, gain)| {
+                            let m = m * gain;
+                            let emphasized =
+                                m - pre_emphasis_coefficient * channel.pre_emphasis_state;
+                            channel.pre_emphasis_state = m;
+                            emphasized
+                        }),
+                );
+
+            // The spectral match compares how the energy above and below the center of the band
+            // range is balanced in the raw modulator and in the carrier the bands see
+            for (m, c) in modulator.iter().zip(&self.carrier_scratch) {
+                channel.modulator_tilt_state = flush_denormal(
+                    channel.modulator_tilt_state
+                        + tilt_split_coefficient * (m - channel.modulator_tilt_state),
+                );
+                channel.carrier_tilt_state = flush_denormal(
+                    channel.carrier_tilt_state
+                        + tilt_split_coefficient * (c - channel.carrier_tilt_state),
+                );
+
+                for (energy, sample) in tilt_energies.iter_mut().zip([
+                    channel.modulator_tilt_state,
+                    m - channel.modulator_tilt_state,
+                    channel.carrier_tilt_state,
+                    c - channel.carrier_tilt_state,
+                ]) {
+                    *energy += f64::from(sample) * f64::from(sample);
+                }
+            }
+
+            // The ducker follows the modulator's overall level and turns the wet signal down as
+            // the modulator goes quiet, so the carrier doesn't ring through between phrases. The
+            // envelope keeps running at 0% depth so turning the depth up doesn't start out with a
+            // stale level.
+            for ((m, gain), duck_gain) in modulator
+                .iter()
+                .zip(&self.modulator_gain_scratch)
+                .zip(&mut channel.duck_gains)
+            {
+                let level = (m * gain).abs();
+                let coefficient = if level > channel.duck_envelope {
+                    duck_attack
+                } else {
+                    duck_release
+                };
+                channel.duck_envelope = flush_denormal(
+                    channel.duck_envelope + coefficient * (level - channel.duck_envelope),
+                );
+
+                let openness = (channel.duck_envelope / DUCK_THRESHOLD).min(1.0);
+                *duck_gain = 1.0 - duck_depth * (1.0 - openness);
+            }
+
+            // The air band works like one more band above the top band. It's processed at the
+            // original sample rate and added to the wet signal afterwards, so it works the same
+            // way with both engines. Like the ducker it keeps running at 0%.
+            for ((m, c), air) in self
+                .modulator_scratch
+                .iter()
+                .zip(&self.carrier_scratch)
+                .zip(&mut channel.air)
+            {
+                let level = (channel.modulator_air_highpass.process(f64::from(*m)) as f32).abs();
+                let coefficient = if level > channel.air_envelope {
+                    air_attack
+                } else {
+                    air_release
+                };
+                channel.air_envelope = flush_denormal(
+                    channel.air_envelope + coefficient * (level - channel.air_envelope),
+                );
+
+                let carrier_air = channel.carrier_air_highpass.process(f64::from(*c)) as f32;
+                *air = carrier_air * channel.air_envelope * air_amount;
+            }
+
+            if engines.fft {
+                channel.spectral.set_window(fft_window);
+                channel.spectral.set_overlap(fft_overlap);
+                channel.spectral.process(
+                    &self.modulator_scratch,
+                    &self.carrier_scratch,
+                    &mut channel.spectral_output[..num_samples],
+                    &mut channel.spectral_levels,
+                    SpectralSettings {
+                        band_gains: &self.band_gains[channel_idx % 2],
+                        ..spectral_settings
+                    },
+                );
+                if !engines.filter_bank {
+                    channel
+                        .band_amplitudes
+                        .copy_from_slice(&channel.spectral_levels);
+                    continue;
+                }
+            }
+
+            let modulator_buffer = &mut channel.modulator_buffer[..num_oversampled_samples];
+            let carrier_buffer = &mut channel.carrier_buffer[..num_oversampled_samples];
+            if oversampling_stages == 0 {
+                modulator_buffer.copy_from_slice(&self.modulator_scratch);
+                carrier_buffer.copy_from_slice(&self.carrier_scratch);
+            } else {
+                channel.modulator_upsampler.process(
+                    &self.modulator_scratch,
+                    modulator_buffer,
+                    oversampling_stages,
+                );
+                channel.carrier_upsampler.process(
+                    &self.carrier_scratch,
+                    carrier_buffer,
+                    oversampling_stages,
+                );
+            }
+        }
+
+        self.update_carrier_tilt_difference(tilt_energies, num_samples);
+
+        // The FFT engine has already written its output
+        if engines.filter_bank {
+            // The host may split the buffer into arbitrarily small blocks for sample accurate
+            // automation. All filter, envelope, and resampler state carries over between blocks,
+            // and the band gains ramp over every block, so the split points don't leave any traces.
+            self.start_band_gain_ramp(num_oversampled_samples);
+            let num_bands = self.prototype_bank.num_bands();
+            let envelope_settings = EnvelopeSettings {
+                shape: envelope_shape,
+                attack_rates: &self.band_attack_rates,
+                release_rates: &self.band_release_rates,
+                rate_scale: 1.0,
+                hold_samples: band_hold_samples,
+                decay_rate: band_decay_rate,
+                rms_coefficient: match self.params.envelope_mode.value() {
+                    EnvelopeMode::Peak => None,
+                    EnvelopeMode::Rms => {
+                        Some(one_pole_coefficient(release_ms, processing_sample_rate))
+                    }
                 },
-            ),
-            bands: IntParam::new("Number of Bands", 20, IntRange::Linear { min: 1, max: 256 }),
+                gate_open_level,
+                gate_close_level,
+                spectral_smoothing: self.params.spectral_smoothing.value(),
+            };
+
+            // The channels are processed in lockstep so they can share envelopes
+            for sample_idx in 0..num_oversampled_samples {
+                for (gains, steps) in self.band_gains.iter_mut().zip(&self.band_gain_steps) {
+                    for (gain, step) in gains[..num_bands].iter_mut().zip(steps) {
+                        *gain += step;
+                    }
+                }
+
+                // Freezing slows the envelope followers down until they stop moving entirely
+                self.freeze_amount +=
+                    (freeze_target - self.freeze_amount).clamp(-freeze_step, freeze_step);
+                let envelope_settings = EnvelopeSettings {
+                    rate_scale: 1.0 - self.freeze_amount,
+                    ..envelope_settings
+                };
+
+                if modulator_mono {
+                    self.channels[0].analyze_sample(sample_idx, envelope_settings);
+                } else {
+                    for channel in &mut self.channels {
+                        channel.analyze_sample(sample_idx, envelope_settings);
+                    }
+
+                    if env_link {
+                        for band_idx in 0..num_bands {
+                            let amplitude = self
+                                .channels
+                                .iter()
+                                .map(|channel| channel.band_amplitudes[band_idx])
+                                .fold(0.0, f32::max);
+                            for channel in &mut self.channels {
+                                channel.band_amplitudes[band_idx] = amplitude;
+                            }
+                        }
+                    }
+                }
+
+                let (first_channel, other_channels) = self
+                    .channels
+                    .split_first_mut()
+                    .expect("There's always at least one channel");
+                first_channel.synthesize_sample(
+                    sample_idx,
+                    None,
+                    &self.formant_map,
+                    &self.band_gains[0],
+                    synthesis_settings,
+                );
+                for (channel_idx, channel) in other_channels.iter_mut().enumerate() {
+                    channel.synthesize_sample(
+                        sample_idx,
+                        modulator_mono.then_some(&*first_channel),
+                        &self.formant_map,
+                        &self.band_gains[(channel_idx + 1) % 2],
+                        synthesis_settings,
+                    );
+                }
+            }
+
+            // This gets rid of any rounding errors the ramp accumulated
+            self.snap_band_gains();
+        }
+
+        if ms_mode {
+            for channel_pair in self.channels.chunks_exact_mut(2) {
+                let [mid, side] = channel_pair else {
+                    unreachable!()
+                };
+                if engines.filter_bank {
+                    decode_mid_side(
+                        &mut mid.output_buffer[..num_oversampled_samples],
+                        &mut side.output_buffer[..num_oversampled_samples],
+                    );
+                }
+                if engines.fft {
+                    decode_mid_side(
+                        &mut mid.spectral_output[..num_samples],
+                        &mut side.spectral_output[..num_samples],
+                    );
+                }
+            }
+        }
+
+        for (modulator, channel) in channels.iter_mut().zip(self.channels.iter_mut()) {
+            let num_samples = modulator.len();
+            let wet = if engines.filter_bank {
+                let output_buffer = &channel.output_buffer[..num_samples << oversampling_stages];
+                let wet = if oversampling_stages == 0 {
+                    output_buffer
+                } else {
+                    let output = &mut self.output_scratch[..num_samples];
+                    channel
+                        .output_downsampler
+                        .process(output_buffer, output, oversampling_stages);
+                    output
+                };
+
+                // While blending, the FFT engine's output buffer is reused for the mixed output
+                if engines.fft {
+                    for ((fft_sample, filter_bank_sample), engine_blend) in channel.spectral_output
+                        [..num_samples]
+                        .iter_mut()
+                        .zip(wet)
+                        .zip(&self.engine_blend_scratch)
+                    {
+                        let filter_bank_sample = channel
+                            .filter_bank_delay
+                            .process(*filter_bank_sample, filter_bank_delay);
+                        *fft_sample =
+                            filter_bank_sample + (*fft_sample - filter_bank_sample) * engine_blend;
+                    }
+
+                    &channel.spectral_output[..num_samples]
+                } else {
+                    wet
+                }
+            } else {
+                &channel.spectral_output[..num_samples]
+            };
+
+            // The modulator still holds the dry signal at this point. The bypassed carrier bass
+            // and the air band become part of the wet signal. With the dry crossover the wet
+            // signal below the crossover frequency is replaced by the dry signal.
+            for (sample_idx, ((((((sample, wet), bass), air), mix), duck_gain), gate_sync_gain)) in
+                modulator
+                    .iter_mut()
+                    .zip(wet)
+                    .zip(&channel.carrier_bass)
+                    .zip(&channel.air)
+                    .zip(&self.dry_wet_scratch)
+                    .zip(&channel.duck_gains)
+                    .zip(&self.gate_sync_scratch)
+                    .enumerate()
+            {
+                let eco_switch_gain = (self.eco_switch_gain
+                    + eco_switch_step * (sample_idx + 1) as f32)
+                    .clamp(0.0, 1.0);
+                let dry = channel.dry_delay.process(*sample, dry_delay);
+                let bass = channel.carrier_bass_delay.process(*bass, dry_delay);
+                let air = channel.air_delay.process(*air, engine_delay);
+                let mut wet = (wet * eco_switch_gain + bass + air) * duck_gain * gate_sync_gain;
+                if let Some(coefficient) = crossover_coefficient {
+                    channel.crossover_dry_state = flush_denormal(
+                        channel.crossover_dry_state
+                            + coefficient * (dry - channel.crossover_dry_state),
+                    );
+                    channel.crossover_wet_state = flush_denormal(
+                        channel.crossover_wet_state
+                            + coefficient * (wet - channel.crossover_wet_state),
+                    );
+                    wet = wet - channel.crossover_wet_state + channel.crossover_dry_state;
+                }
+
+                *sample = dry + (wet - dry) * mix;
+            }
         }
+        self.eco_switch_gain =
+            (self.eco_switch_gain + eco_switch_step * num_samples as f32).clamp(0.0, 1.0);
+
+        true
     }
 }
 
@@ -72,27 +3923,65 @@ impl Plugin for StarlightVocoder {
 
     // The first audio IO layout is used as the default. The other layouts may be selected either
     // explicitly or automatically by the host or the user depending on the plugin API/backend.
+    //
+    // The modulator (usually a voice) comes in on the main input, and the carrier (a synth, pad,
+    // etc.) comes in on the sidechain input.
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(2),
             main_output_channels: NonZeroU32::new(2),
 
-            aux_input_ports: &[],
+            aux_input_ports: &[new_nonzero_u32(2)],
             aux_output_ports: &[],
 
             // Individual ports and the layout as a whole can be named here. By default these names
             // are generated as needed. This layout will be called 'Stereo', while the other one is
             // given the name 'Mono' based no the number of input and output channels.
-            names: PortNames::const_default(),
+            names: PortNames {
+                aux_inputs: &["Carrier"],
+                ..PortNames::const_default()
+            },
         },
         AudioIOLayout {
             main_input_channels: NonZeroU32::new(1),
             main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                aux_inputs: &["Carrier"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        // A mono carrier is duplicated to both output channels, which are still processed
+        // independently
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                layout: Some("Stereo, Mono Carrier"),
+                aux_inputs: &["Carrier"],
+                ..PortNames::const_default()
+            },
+            ..AudioIOLayout::const_default()
+        },
+        // Every channel has its own filter and envelope state, so the number of main channels
+        // doesn't matter to the DSP
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(4),
+            main_output_channels: NonZeroU32::new(4),
+            aux_input_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                layout: Some("Quad"),
+                aux_inputs: &["Carrier"],
+                ..PortNames::const_default()
+            },
             ..AudioIOLayout::const_default()
         },
     ];
 
-    const MIDI_INPUT: MidiConfig = MidiConfig::None;
+    // Notes are used to play the internal carrier synth, and SysEx messages can control the bands
+    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
     // Setting this to `true` will tell the wrapper to split the buffer up into smaller blocks
     // whenever there are inter-buffer parameter changes. This way no changes to the plugin are
     // required to support sample accurate automation and the wrapper handles all of the boring
@@ -100,66 +3989,89 @@ impl Plugin for StarlightVocoder {
     // splits.
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
-    // If the plugin can send or receive SysEx messages, it can define a type to wrap around those
-    // messages here. The type implements the `SysExMessage` trait, which allows conversion to and
-    // from plain byte buffers.
-    type SysExMessage = ();
+    // The band gains and the freeze state can be controlled over SysEx. The message format is
+    // documented in the `sysex` module.
+    type SysExMessage = BandControlMessage;
     // More advanced plugins can use this to run expensive background tasks. See the field's
     // documentation for more information. `()` means that the plugin does not have any background
     // tasks.
     type BackgroundTask = ();
 
     fn params(&self) -> Arc<dyn Params> {
-        self.params.clone()
+        self.vocoder.params.clone()
     }
 
-    // This plugin doesn't need any special initialization, but if you need to do anything expensive
-    // then this would be the place. State is kept around when the host reconfigures the
-    // plugin. If we do need special initialization, we could implement the `initialize()` and/or
-    // `reset()` methods
+    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(
+            self.vocoder.params.clone(),
+            self.vocoder.band_levels.clone(),
+            self.vocoder.effective_bands.clone(),
+            self.vocoder.input_peak.clone(),
+            self.vocoder.output_peak.clone(),
+            self.vocoder.correlation.clone(),
+            self.vocoder.params.editor_state.clone(),
+        )
+    }
 
-    fn process(
+    // State is kept around when the host reconfigures the plugin, so anything that depends on the
+    // buffer config needs to be recomputed here
+    fn initialize(
         &mut self,
-        buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
-    ) -> ProcessStatus {
-        for channel_samples in buffer.iter_samples() {
-            // Smoothing is optionally built into the parameters themselves
-            // let gain = self.params.gain.smoothed.next();
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
+    ) -> bool {
+        let num_channels = audio_io_layout
+            .main_output_channels
+            .map(NonZeroU32::get)
+            .unwrap_or_default() as usize;
+        let config = VocoderConfig {
+            sample_rate: buffer_config.sample_rate,
+            max_block_size: buffer_config.max_buffer_size as usize,
+            offline: buffer_config.process_mode == ProcessMode::Offline,
+        };
+        self.vocoder.initialize(&config, num_channels);
 
-            // for sample in channel_samples {
-            //     *sample *= gain;
-            // }
-
-            let mut samples_f32: Vec<&mut f32> = channel_samples.into_iter().collect();
-            let q: f64 = 300f64;
-            let bp_filter = Filter::new(
-                1,
-                // self.buffer_config.sample_rate.into(),
-                1.0,
-                Cutoff::BandPass(
-                    self.params.low_freq_cutoff.value().into(),
-                    f64::from(self.params.low_freq_cutoff.value()) + q,
-                ),
-            )
-            .unwrap();
+        self.latency_samples = self.vocoder.latency_samples();
+        context.set_latency_samples(self.latency_samples);
 
-            let samples_f64: Vec<f64> = samples_f32.iter().map(|f| f64::from(**f)).collect();
+        true
+    }
 
-            let filter_res: Vec<f32> = bp_filter
-                .bidirectional(&samples_f64)
-                .unwrap()
-                .iter()
-                .map(|f| *f as f32)
-                .collect();
+    // `reset()` is always called after `initialize()`, and the sample rate can only change in
+    // `initialize()`. This clears all state that depends on previous input, so the first sample
+    // after a transport restart or a loop is processed as if the plugin was only fed silence.
+    fn reset(&mut self) {
+        self.vocoder.reset();
+    }
 
-            for (i, f) in filter_res.iter().enumerate() {
-                *samples_f32[i] = *f;
-            }
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        // The host needs to be informed whenever a parameter change affects the latency
+        let latency_samples = self.vocoder.latency_samples();
+        if latency_samples != self.latency_samples {
+            self.latency_samples = latency_samples;
+            context.set_latency_samples(latency_samples);
         }
 
-        ProcessStatus::Normal
+        let transport = context.transport();
+        let song_position = match (transport.playing, transport.pos_beats(), transport.tempo) {
+            (true, Some(beats), Some(tempo)) => Some(SongPosition { beats, tempo }),
+            _ => None,
+        };
+        let sidechain = aux
+            .inputs
+            .first()
+            .map(|sidechain| sidechain.as_slice_immutable());
+
+        self.vocoder
+            .process(buffer.as_slice(), sidechain, song_position, || {
+                context.next_event()
+            })
     }
 
     // This can be used for cleaning up special resources like socket connections whenever the
@@ -172,8 +4084,12 @@ impl ClapPlugin for StarlightVocoder {
     const CLAP_DESCRIPTION: Option<&'static str> = Some("A simple vocoder");
     const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
     const CLAP_SUPPORT_URL: Option<&'static str> = None;
+    // The plugin is an effect when vocoding the sidechain, but with the internal synth it's played
+    // like an instrument. The note port itself comes from `MIDI_INPUT`.
     const CLAP_FEATURES: &'static [ClapFeature] = &[
         ClapFeature::AudioEffect,
+        ClapFeature::Instrument,
+        ClapFeature::Synthesizer,
         ClapFeature::Stereo,
         ClapFeature::Mono,
         ClapFeature::Utility,
@@ -182,9 +4098,755 @@ impl ClapPlugin for StarlightVocoder {
 
 impl Vst3Plugin for StarlightVocoder {
     const VST3_CLASS_ID: [u8; 16] = *b"StarlightVocoder";
-    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
-        &[Vst3SubCategory::Fx, Vst3SubCategory::Filter];
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] = &[
+        Vst3SubCategory::Fx,
+        Vst3SubCategory::Filter,
+        Vst3SubCategory::Synth,
+    ];
 }
 
 nih_export_clap!(StarlightVocoder);
 nih_export_vst3!(StarlightVocoder);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+    const MAX_BLOCK_SIZE: usize = 512;
+
+    fn test_config(max_block_size: usize) -> VocoderConfig {
+        VocoderConfig {
+            sample_rate: SAMPLE_RATE,
+            max_block_size,
+            offline: false,
+        }
+    }
+
+    /// A vocoder with `num_channels` channels that uses `params` instead of the default
+    /// parameters.
+    fn vocoder_with_params(params: StlVocoderParams, num_channels: usize) -> Vocoder {
+        let mut vocoder = Vocoder::unconfigured();
+        vocoder.params = Arc::new(params);
+        vocoder.initialize(&test_config(MAX_BLOCK_SIZE), num_channels);
+        vocoder.reset();
+
+        vocoder
+    }
+
+    /// A modulator with a slowly moving pitch and level.
+    fn test_modulator(num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|idx| {
+                let time = idx as f32 / SAMPLE_RATE;
+                (TAU * (220.0 * time + 30.0 * (TAU * 0.5 * time).sin())).sin()
+                    * (0.3 + 0.2 * (TAU * 3.0 * time).sin())
+            })
+            .collect()
+    }
+
+    /// A 110 Hz sawtooth carrier, which has energy in every band.
+    fn test_carrier(num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|idx| ((idx as f32 * 110.0 / SAMPLE_RATE).fract() * 2.0 - 1.0) * 0.5)
+            .collect()
+    }
+
+    fn bands_param(bands: i32) -> IntParam {
+        IntParam::new(
+            "Number of Bands",
+            bands,
+            IntRange::Linear {
+                min: 1,
+                max: MAX_BANDS as i32,
+            },
+        )
+    }
+
+    #[test]
+    fn band_count_sets_the_number_of_filters() {
+        for bands in [1, 4, 20, 64] {
+            let vocoder = vocoder_with_params(
+                StlVocoderParams {
+                    bands: bands_param(bands),
+                    ..Default::default()
+                },
+                1,
+            );
+
+            assert_eq!(vocoder.prototype_bank.num_bands(), bands as usize);
+            assert_eq!(
+                vocoder.channels[0].modulator_bank.num_bands(),
+                bands as usize
+            );
+        }
+    }
+
+    #[test]
+    fn decaying_envelopes_never_turn_denormal() {
+        let mut vocoder = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        let mut modulator = test_modulator(SAMPLE_RATE as usize * 10);
+        modulator[SAMPLE_RATE as usize..].fill(0.0);
+        let carrier = test_carrier(modulator.len());
+
+        for (modulator_block, carrier_block) in modulator
+            .chunks(MAX_BLOCK_SIZE)
+            .zip(carrier.chunks(MAX_BLOCK_SIZE))
+        {
+            let output = vocoder.process_block(modulator_block, carrier_block);
+            assert!(output.iter().all(|sample| !sample.is_subnormal()));
+            assert!(vocoder.channels[0]
+                .band_amplitudes
+                .iter()
+                .all(|amplitude| !amplitude.is_subnormal()));
+        }
+    }
+
+    #[test]
+    fn filters_are_built_for_the_host_sample_rate() {
+        let sample_rate = 48000.0;
+        let mut vocoder = Vocoder::new(VocoderConfig {
+            sample_rate,
+            ..test_config(MAX_BLOCK_SIZE)
+        });
+        vocoder.reset();
+        assert_eq!(vocoder.sample_rate, sample_rate);
+
+        // A sine at a band's center only passes at unity gain if the filter was designed for the
+        // rate it's actually running at
+        let mut filter_bank = vocoder.channels[0].modulator_bank.clone();
+        let (low, high) = filter_bank.band_edges()[5];
+        let center = (low * high).sqrt();
+        let mut peak = 0.0f32;
+        for idx in 0..sample_rate as usize {
+            let sine = (TAU * center * idx as f32 / sample_rate).sin();
+            let band = filter_bank.process_sample(sine).nth(5).unwrap();
+            if idx > sample_rate as usize / 2 {
+                peak = peak.max(band.abs());
+            }
+        }
+        assert!((peak - 1.0).abs() < 0.01, "peak = {peak}");
+    }
+
+    #[test]
+    fn steady_state_processing_reuses_the_scratch_buffers() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+        let mut vocoder = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        vocoder.process_block(&modulator[..MAX_BLOCK_SIZE], &carrier[..MAX_BLOCK_SIZE]);
+
+        // Any reallocation would move or grow one of these buffers
+        let scratch_buffers = |vocoder: &Vocoder| {
+            [
+                &vocoder.mono_modulator_scratch,
+                &vocoder.modulator_scratch,
+                &vocoder.carrier_scratch,
+                &vocoder.output_scratch,
+                &vocoder.dry_wet_scratch,
+                &vocoder.synth_scratch,
+                &vocoder.main_input_scratch[0],
+            ]
+            .map(|buffer| (buffer.as_ptr(), buffer.capacity()))
+        };
+        let before = scratch_buffers(&vocoder);
+        vocoder.process_block(&modulator, &carrier);
+        assert_eq!(scratch_buffers(&vocoder), before);
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn max_difference(a: &[f32], b: &[f32]) -> f32 {
+        assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_block_size() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+
+        let one_block = Vocoder::new(test_config(4096)).process_block(&modulator, &carrier);
+        let small_blocks = Vocoder::new(test_config(64)).process_block(&modulator, &carrier);
+        assert!(rms(&one_block) > 1e-3);
+        assert!(max_difference(&one_block, &small_blocks) < 1e-6);
+    }
+
+    #[test]
+    fn mono_buffer_produces_output() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let mut vocoder = vocoder_with_params(StlVocoderParams::default(), 1);
+        let output =
+            vocoder.process_block(&test_modulator(num_samples), &test_carrier(num_samples));
+
+        assert_eq!(output.len(), num_samples);
+        assert!(rms(&output[num_samples / 2..]) > 1e-3);
+    }
+
+    #[test]
+    fn persisted_settings_round_trip() {
+        let settings = PersistedSettings {
+            label: String::from("Robot Choir"),
+            band_spacing_curve: 2.5,
+            band_gain_curve: vec![
+                CurvePoint {
+                    frequency: 100.0,
+                    gain_db: -3.0,
+                },
+                CurvePoint {
+                    frequency: 4000.0,
+                    gain_db: 6.0,
+                },
+            ],
+        };
+        let params = StlVocoderParams::default();
+        *params.settings.write().unwrap() = settings.clone();
+
+        let restored = StlVocoderParams::default();
+        restored.deserialize_fields(&params.serialize_fields());
+        assert_eq!(*restored.settings.read().unwrap(), settings);
+    }
+
+    fn filter_order_param(order: i32) -> IntParam {
+        IntParam::new(
+            "Filter Order",
+            order,
+            IntRange::Linear {
+                min: 1,
+                max: MAX_FILTER_ORDER as i32,
+            },
+        )
+    }
+
+    fn cutoff_param(name: &str, frequency: f32) -> FloatParam {
+        FloatParam::new(
+            name,
+            frequency,
+            FloatRange::Skewed {
+                min: MIN_BAND_FREQUENCY,
+                max: 20000.0,
+                factor: FloatRange::skew_factor(-2.0),
+            },
+        )
+    }
+
+    #[test]
+    fn pathological_settings_only_output_finite_samples() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let mut vocoder = vocoder_with_params(
+            StlVocoderParams {
+                bands: bands_param(MAX_BANDS as i32),
+                filter_order: filter_order_param(MAX_FILTER_ORDER as i32),
+                low_freq_cutoff: cutoff_param("Formant lower end", MIN_BAND_FREQUENCY),
+                high_freq_cutoff: cutoff_param("Formant upper end", 20000.0),
+                band_q: FloatParam::new(
+                    "Band Q",
+                    4.0,
+                    FloatRange::Linear {
+                        min: 0.25,
+                        max: 4.0,
+                    },
+                ),
+                ..Default::default()
+            },
+            1,
+        );
+
+        // Huge inputs with the odd non-finite sample in between
+        let mut modulator: Vec<f32> = test_modulator(num_samples)
+            .into_iter()
+            .map(|sample| sample * 1e6)
+            .collect();
+        modulator[1000] = f32::NAN;
+        modulator[2000] = f32::INFINITY;
+        modulator[3000] = f32::NEG_INFINITY;
+        let mut carrier: Vec<f32> = test_carrier(num_samples)
+            .into_iter()
+            .map(|sample| sample * 1e6)
+            .collect();
+        carrier[1500] = f32::NAN;
+
+        let output = vocoder.process_block(&modulator, &carrier);
+        assert!(output.iter().all(|sample| sample.is_finite()));
+    }
+
+    /// Parameters that process every channel on its own, without summing the modulator to mono
+    /// or linking the channels' envelopes.
+    fn independent_channel_params() -> StlVocoderParams {
+        StlVocoderParams {
+            modulator_mono: BoolParam::new("Mono Modulator", false),
+            env_link: BoolParam::new("Link Envelopes", false),
+            ..Default::default()
+        }
+    }
+
+    /// Vocode every channel in `modulators` with the matching channel in `carriers`, in blocks of
+    /// at most `block_size` samples.
+    fn process_channels(
+        vocoder: &mut Vocoder,
+        modulators: &[Vec<f32>],
+        carriers: &[Vec<f32>],
+        block_size: usize,
+    ) -> Vec<Vec<f32>> {
+        let mut output = modulators.to_vec();
+        let num_samples = output[0].len();
+        for block_start in (0..num_samples).step_by(block_size) {
+            let block = block_start..(block_start + block_size).min(num_samples);
+            let mut buffer: Vec<&mut [f32]> = output
+                .iter_mut()
+                .map(|channel| &mut channel[block.clone()])
+                .collect();
+            let sidechain: Vec<&[f32]> = carriers
+                .iter()
+                .map(|channel| &channel[block.clone()])
+                .collect();
+            vocoder.process(&mut buffer, Some(&sidechain), None, || None);
+        }
+
+        output
+    }
+
+    #[test]
+    fn every_channel_of_a_four_channel_buffer_is_processed_independently() {
+        const NUM_CHANNELS: usize = 4;
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulators: Vec<Vec<f32>> = (0..NUM_CHANNELS)
+            .map(|channel_idx| {
+                test_modulator(num_samples)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, sample)| {
+                        let gate = if (idx / 2000) % NUM_CHANNELS == channel_idx {
+                            1.0
+                        } else {
+                            0.3
+                        };
+                        sample * gate
+                    })
+                    .collect()
+            })
+            .collect();
+        let carriers: Vec<Vec<f32>> = (0..NUM_CHANNELS)
+            .map(|channel_idx| {
+                let pitch = 1.0 + channel_idx as f32 * 0.25;
+                (0..num_samples)
+                    .map(|idx| (idx as f32 * 110.0 * pitch / SAMPLE_RATE).fract() - 0.5)
+                    .collect()
+            })
+            .collect();
+
+        let mut vocoder = vocoder_with_params(independent_channel_params(), NUM_CHANNELS);
+        let output = process_channels(&mut vocoder, &modulators, &carriers, MAX_BLOCK_SIZE);
+
+        // Each channel's output only depends on that channel's inputs, so it's the same when all
+        // other channels are silent
+        for channel_idx in 0..NUM_CHANNELS {
+            let solo_modulators: Vec<Vec<f32>> = (0..NUM_CHANNELS)
+                .map(|idx| {
+                    if idx == channel_idx {
+                        modulators[idx].clone()
+                    } else {
+                        vec![0.0; num_samples]
+                    }
+                })
+                .collect();
+            let mut vocoder = vocoder_with_params(independent_channel_params(), NUM_CHANNELS);
+            let solo_output =
+                process_channels(&mut vocoder, &solo_modulators, &carriers, MAX_BLOCK_SIZE);
+
+            assert!(rms(&output[channel_idx]) > 1e-3);
+            assert!(max_difference(&solo_output[channel_idx], &output[channel_idx]) < 1e-6);
+            for (idx, channel) in solo_output.iter().enumerate() {
+                if idx != channel_idx {
+                    assert_eq!(rms(channel), 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn irregular_automation_splits_are_seamless() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+        let automated_params = || {
+            Arc::new(StlVocoderParams {
+                low_freq_cutoff: cutoff_param("Formant lower end", 150.0),
+                high_freq_cutoff: cutoff_param("Formant upper end", 6000.0),
+                ..Default::default()
+            })
+        };
+
+        // The parameters change halfway through, which is where the host would split the block
+        let halfway = num_samples / 2;
+        let mut whole_blocks = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        let mut expected = whole_blocks.process_block(&modulator[..halfway], &carrier[..halfway]);
+        whole_blocks.params = automated_params();
+        expected.extend(whole_blocks.process_block(&modulator[halfway..], &carrier[halfway..]));
+
+        let mut split_blocks = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        let mut output = Vec::with_capacity(num_samples);
+        let block_sizes = [1, 7, 64, 2, 300, 13, 512, 1, 1, 129];
+        let mut block_start = 0;
+        for block_size in block_sizes.into_iter().cycle() {
+            if block_start == num_samples {
+                break;
+            }
+            if block_start == halfway {
+                split_blocks.params = automated_params();
+            }
+
+            let boundary = if block_start < halfway {
+                halfway
+            } else {
+                num_samples
+            };
+            let block = block_start..(block_start + block_size).min(boundary);
+            block_start = block.end;
+            output.extend(split_blocks.process_block(&modulator[block.clone()], &carrier[block]));
+        }
+
+        assert!(rms(&expected) > 1e-3);
+        assert!(max_difference(&output, &expected) < 1e-6);
+    }
+
+    #[test]
+    fn stereo_channels_do_not_bleed_into_each_other() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let carriers = [test_carrier(num_samples), test_carrier(num_samples)];
+
+        // A silent right modulator stays silent next to a loud left one
+        let mut vocoder = vocoder_with_params(independent_channel_params(), 2);
+        let left_only = process_channels(
+            &mut vocoder,
+            &[test_modulator(num_samples), vec![0.0; num_samples]],
+            &carriers,
+            MAX_BLOCK_SIZE,
+        );
+        assert!(rms(&left_only[0]) > 1e-3);
+        assert_eq!(rms(&left_only[1]), 0.0);
+
+        // And changing the right modulator doesn't change the left output
+        let right_modulator: Vec<f32> = test_modulator(num_samples)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, sample)| sample * (idx as f32 * 0.0007).sin())
+            .collect();
+        let mut vocoder = vocoder_with_params(independent_channel_params(), 2);
+        let both = process_channels(
+            &mut vocoder,
+            &[test_modulator(num_samples), right_modulator],
+            &carriers,
+            MAX_BLOCK_SIZE,
+        );
+        assert!(rms(&both[1]) > 1e-3);
+        assert!(max_difference(&both[0], &left_only[0]) < 1e-6);
+    }
+
+    #[test]
+    fn mono_carrier_is_duplicated_to_both_output_channels() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        // Each channel has its own noise generator, so the sibilance noise is turned off to make
+        // the two channels identical
+        let mut vocoder = vocoder_with_params(
+            StlVocoderParams {
+                sibilance_amount: FloatParam::new(
+                    "Sibilance",
+                    0.0,
+                    FloatRange::Linear { min: 0.0, max: 1.0 },
+                ),
+                ..independent_channel_params()
+            },
+            2,
+        );
+        let output = process_channels(
+            &mut vocoder,
+            &[test_modulator(num_samples), test_modulator(num_samples)],
+            &[test_carrier(num_samples)],
+            MAX_BLOCK_SIZE,
+        );
+
+        assert!(rms(&output[0]) > 1e-3);
+        assert!(max_difference(&output[0], &output[1]) < 1e-6);
+    }
+
+    #[test]
+    fn output_length_matches_the_input_for_every_filter_order() {
+        for order in 1..=MAX_FILTER_ORDER as i32 {
+            let mut vocoder = vocoder_with_params(
+                StlVocoderParams {
+                    filter_order: filter_order_param(order),
+                    ..Default::default()
+                },
+                1,
+            );
+
+            for num_samples in [
+                1,
+                100,
+                MAX_BLOCK_SIZE - 1,
+                MAX_BLOCK_SIZE,
+                MAX_BLOCK_SIZE + 1,
+                2000,
+            ] {
+                let output =
+                    vocoder.process_block(&test_modulator(num_samples), &test_carrier(num_samples));
+                assert_eq!(output.len(), num_samples, "order {order}");
+                assert!(output.iter().all(|sample| sample.is_finite()));
+            }
+        }
+    }
+
+    #[test]
+    fn both_input_routings_vocode_the_same_signals() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+
+        let mut main_is_modulator = vocoder_with_params(StlVocoderParams::default(), 1);
+        let expected = main_is_modulator.process_block(&modulator, &carrier);
+        let mut main_is_carrier = vocoder_with_params(
+            StlVocoderParams {
+                input_routing: EnumParam::new("Input Routing", InputRouting::MainIsCarrier),
+                ..Default::default()
+            },
+            1,
+        );
+        let output = main_is_carrier.process_block(&carrier, &modulator);
+
+        // The main input's DC blocker now filters the carrier instead of the modulator, so the
+        // outputs are only close and not identical
+        let difference: Vec<f32> = output.iter().zip(&expected).map(|(a, b)| a - b).collect();
+        assert!(rms(&expected) > 1e-3);
+        assert!(rms(&difference) < rms(&expected) * 0.05);
+    }
+
+    #[test]
+    fn bypass_delays_the_input_by_the_reported_latency() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let mut vocoder = vocoder_with_params(
+            StlVocoderParams {
+                bypass: BoolParam::new("Bypass", true),
+                lookahead_ms: FloatParam::new(
+                    "Lookahead",
+                    5.0,
+                    FloatRange::Linear {
+                        min: 0.0,
+                        max: MAX_LOOKAHEAD_MS,
+                    },
+                ),
+                ..Default::default()
+            },
+            1,
+        );
+        let latency = vocoder.latency_samples() as usize;
+        assert!(latency > 0);
+
+        let modulator = test_modulator(num_samples);
+        let output = vocoder.process_block(&modulator, &test_carrier(num_samples));
+        assert!(output[..latency].iter().all(|sample| *sample == 0.0));
+        assert_eq!(
+            max_difference(&output[latency..], &modulator[..num_samples - latency]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn empty_buffers_have_no_side_effects() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let halfway = num_samples / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+        let expected =
+            Vocoder::new(test_config(MAX_BLOCK_SIZE)).process_block(&modulator, &carrier);
+
+        let mut vocoder = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        let mut output = vocoder.process_block(&modulator[..halfway], &carrier[..halfway]);
+        let peaks = (
+            vocoder.input_peak.load(Ordering::Relaxed),
+            vocoder.output_peak.load(Ordering::Relaxed),
+        );
+        let status = vocoder.process(&mut [&mut []], Some(&[&[][..]]), None, || None);
+        assert!(matches!(status, ProcessStatus::Normal));
+        assert_eq!(
+            (
+                vocoder.input_peak.load(Ordering::Relaxed),
+                vocoder.output_peak.load(Ordering::Relaxed),
+            ),
+            peaks
+        );
+
+        // The empty buffer didn't advance any of the processing state either
+        output.extend(vocoder.process_block(&modulator[halfway..], &carrier[halfway..]));
+        assert_eq!(max_difference(&output, &expected), 0.0);
+    }
+
+    #[test]
+    fn dc_offsets_are_removed_from_the_output() {
+        let num_samples = SAMPLE_RATE as usize * 2;
+        let modulator: Vec<f32> = test_modulator(num_samples)
+            .into_iter()
+            .map(|sample| sample + 0.4)
+            .collect();
+        let carrier: Vec<f32> = test_carrier(num_samples)
+            .into_iter()
+            .map(|sample| sample + 0.3)
+            .collect();
+
+        let output = Vocoder::new(test_config(MAX_BLOCK_SIZE)).process_block(&modulator, &carrier);
+        let settled = &output[num_samples / 2..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        assert!(rms(settled) > 1e-3);
+        assert!(mean.abs() < 1e-4, "mean = {mean}");
+    }
+
+    #[test]
+    fn inverted_cutoffs_are_swapped() {
+        let mut vocoder = vocoder_with_params(
+            StlVocoderParams {
+                low_freq_cutoff: cutoff_param("Formant lower end", 5000.0),
+                high_freq_cutoff: cutoff_param("Formant upper end", 200.0),
+                ..Default::default()
+            },
+            1,
+        );
+        let expected = vocoder_with_params(
+            StlVocoderParams {
+                low_freq_cutoff: cutoff_param("Formant lower end", 200.0),
+                high_freq_cutoff: cutoff_param("Formant upper end", 5000.0),
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(
+            vocoder.prototype_bank.band_edges(),
+            expected.prototype_bank.band_edges()
+        );
+
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let output =
+            vocoder.process_block(&test_modulator(num_samples), &test_carrier(num_samples));
+        assert!(output.iter().all(|sample| sample.is_finite()));
+    }
+
+    #[test]
+    fn equal_cutoffs_create_a_single_band() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        for frequency in [MIN_BAND_FREQUENCY, 1000.0, 20000.0] {
+            let mut vocoder = vocoder_with_params(
+                StlVocoderParams {
+                    low_freq_cutoff: cutoff_param("Formant lower end", frequency),
+                    high_freq_cutoff: cutoff_param("Formant upper end", frequency),
+                    ..Default::default()
+                },
+                1,
+            );
+            assert_eq!(vocoder.prototype_bank.num_bands(), 1);
+
+            let output =
+                vocoder.process_block(&test_modulator(num_samples), &test_carrier(num_samples));
+            assert!(output.iter().all(|sample| sample.is_finite()));
+        }
+    }
+
+    #[test]
+    fn automating_the_cutoffs_past_each_other_stays_stable() {
+        let num_samples = SAMPLE_RATE as usize / 2;
+        let modulator = test_modulator(num_samples);
+        let carrier = test_carrier(num_samples);
+        let num_blocks = num_samples.div_ceil(MAX_BLOCK_SIZE);
+
+        // The cutoffs move towards each other and cross halfway through
+        let mut vocoder = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        for (block_idx, (modulator_block, carrier_block)) in modulator
+            .chunks(MAX_BLOCK_SIZE)
+            .zip(carrier.chunks(MAX_BLOCK_SIZE))
+            .enumerate()
+        {
+            let sweep = 25f32.powf(block_idx as f32 / num_blocks as f32);
+            vocoder.params = Arc::new(StlVocoderParams {
+                low_freq_cutoff: cutoff_param("Formant lower end", 200.0 * sweep),
+                high_freq_cutoff: cutoff_param("Formant upper end", 5000.0 / sweep),
+                ..Default::default()
+            });
+
+            let output = vocoder.process_block(modulator_block, carrier_block);
+            assert!(output
+                .iter()
+                .all(|sample| sample.is_finite() && sample.abs() <= 1.0));
+        }
+    }
+
+    #[test]
+    fn character_settings_are_valid_parameter_values() {
+        let params = StlVocoderParams::default();
+        let is_in_range = |param: &FloatParam, value: f32| {
+            (param.preview_plain(param.preview_normalized(value)) - value).abs() < 1e-4
+        };
+        for character_idx in 0..Character::variants().len() {
+            let settings = Character::from_index(character_idx).settings();
+            assert!(is_in_range(&params.band_q, settings.band_q));
+            assert!(is_in_range(
+                &params.sibilance_amount,
+                settings.sibilance_amount
+            ));
+            assert!(is_in_range(&params.pre_emphasis, settings.pre_emphasis));
+        }
+
+        // Selecting the neutral character undoes the other characters
+        let neutral = Character::Neutral.settings();
+        assert_eq!(neutral.band_q, params.band_q.default_plain_value());
+        assert_eq!(neutral.spacing, params.spacing.default_plain_value());
+        assert_eq!(
+            neutral.sibilance_amount,
+            params.sibilance_amount.default_plain_value()
+        );
+        assert_eq!(
+            neutral.pre_emphasis,
+            params.pre_emphasis.default_plain_value()
+        );
+    }
+
+    /// The largest third difference between neighbouring samples. This is tiny for a steady sine,
+    /// so a jump in the output or in its slope stands out.
+    fn max_third_difference(samples: &[f32]) -> f32 {
+        samples
+            .windows(4)
+            .map(|window| (window[3] - 3.0 * window[2] + 3.0 * window[1] - window[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn switching_the_band_spacing_crossfades_the_filters() {
+        let num_samples = SAMPLE_RATE as usize;
+        let switch_idx = num_samples / 2;
+        let sine: Vec<f32> = (0..num_samples)
+            .map(|idx| (TAU * 500.0 * idx as f32 / SAMPLE_RATE).sin() * 0.5)
+            .collect();
+
+        let mut vocoder = Vocoder::new(test_config(MAX_BLOCK_SIZE));
+        let mut output = vocoder.process_block(&sine[..switch_idx], &sine[..switch_idx]);
+        vocoder.params = Arc::new(StlVocoderParams {
+            spacing: EnumParam::new("Band Spacing", BandSpacing::Bark),
+            ..Default::default()
+        });
+        output.extend(vocoder.process_block(&sine[switch_idx..], &sine[switch_idx..]));
+
+        // The windows before and after the switch only contain the steady state for the old and
+        // the new spacing
+        let before = max_third_difference(&output[switch_idx - 4410..switch_idx]);
+        let after = max_third_difference(&output[switch_idx + 8820..switch_idx + 13230]);
+        let switch = max_third_difference(&output[switch_idx - 3..switch_idx + 2000]);
+        assert!(before > 0.0 && after > 0.0);
+        assert!(
+            switch < before.max(after) * 2.0,
+            "{switch} vs {before} and {after}"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! A lookahead brickwall limiter for the final output.
+//!
+//! For every sample the limiter computes the gain that would bring the loudest channel down to the
+//! ceiling. The gain that's actually applied is the minimum of these gains over the lookahead
+//! window, smoothed with a moving average over that same window. Every gain that goes into the
+//! average for a sample is at most that sample's own required gain, so the smoothed gain is always
+//! low enough by the time the sample leaves the delay line. The gain then recovers with a one-pole
+//! release.
+
+/// A lookahead limiter with a fixed ceiling. All channels share the same gain, so the stereo image
+/// doesn't shift while limiting.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    /// The highest allowed output level, as a linear gain.
+    ceiling: f32,
+    release_coefficient: f32,
+    /// The number of samples the input is delayed by. The gain is computed over windows of
+    /// `lookahead + 1` samples.
+    lookahead: usize,
+
+    /// The delayed input for every channel, each `lookahead` samples long.
+    delays: Vec<Vec<f32>>,
+    /// The current position in `delays`.
+    delay_pos: usize,
+    /// The required gains in the current window that can still become the window's minimum, as
+    /// `(sample_count, gain)` pairs. This is a ring buffer starting at `queue_head`, and the gains
+    /// in it are increasing.
+    min_queue: Vec<(usize, f32)>,
+    queue_head: usize,
+    queue_len: usize,
+    /// The window minimums for the last `lookahead + 1` samples and their sum, for the moving
+    /// average.
+    minimums: Vec<f32>,
+    minimum_sum: f64,
+    minimum_pos: usize,
+    /// The number of samples processed since the last reset.
+    sample_count: usize,
+    /// The gain applied to the delayed input.
+    gain: f32,
+}
+
+impl Limiter {
+    /// Create a limiter for `num_channels` channels that keeps the output below `ceiling`, looking
+    /// ahead by `lookahead_ms` and recovering over `release_ms`.
+    pub fn new(
+        num_channels: usize,
+        sample_rate: f32,
+        ceiling: f32,
+        lookahead_ms: f32,
+        release_ms: f32,
+    ) -> Self {
+        let lookahead = ((lookahead_ms / 1000.0 * sample_rate).round() as usize).max(1);
+
+        Self {
+            ceiling,
+            release_coefficient: 1.0 - (-1.0 / (release_ms / 1000.0 * sample_rate)).exp(),
+            lookahead,
+
+            delays: vec![vec![0.0; lookahead]; num_channels],
+            delay_pos: 0,
+            min_queue: vec![(0, 1.0); lookahead + 1],
+            queue_head: 0,
+            queue_len: 0,
+            minimums: vec![1.0; lookahead + 1],
+            minimum_sum: (lookahead + 1) as f64,
+            minimum_pos: 0,
+            sample_count: 0,
+            gain: 1.0,
+        }
+    }
+
+    /// The latency the lookahead adds, in samples.
+    pub fn latency_samples(&self) -> u32 {
+        self.lookahead as u32
+    }
+
+    /// Limit the channels in `buffer` in place. The channels need to be equally long.
+    pub fn process(&mut self, buffer: &mut [&mut [f32]]) {
+        let num_samples = buffer.first().map_or(0, |channel| channel.len());
+        let window = self.lookahead + 1;
+        for sample_idx in 0..num_samples {
+            let peak = buffer.iter().fold(0.0, |peak: f32, channel| {
+                peak.max(channel[sample_idx].abs())
+            });
+            let required_gain = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+
+            // The oldest gain drops out once it's no longer in the window. This happens before the
+            // new gain is added so the queue never holds more than a window's worth of gains.
+            // Gains that are higher than the new gain can never become the minimum again.
+            if self.queue_len > 0 && self.min_queue[self.queue_head].0 + window <= self.sample_count
+            {
+                self.queue_head = (self.queue_head + 1) % window;
+                self.queue_len -= 1;
+            }
+            while self.queue_len > 0
+                && self.min_queue[(self.queue_head + self.queue_len - 1) % window].1
+                    >= required_gain
+            {
+                self.queue_len -= 1;
+            }
+            self.min_queue[(self.queue_head + self.queue_len) % window] =
+                (self.sample_count, required_gain);
+            self.queue_len += 1;
+            let minimum = self.min_queue[self.queue_head].1;
+            self.sample_count += 1;
+
+            self.minimum_sum += f64::from(minimum) - f64::from(self.minimums[self.minimum_pos]);
+            self.minimums[self.minimum_pos] = minimum;
+            self.minimum_pos = (self.minimum_pos + 1) % window;
+            let target_gain = (self.minimum_sum / window as f64) as f32;
+
+            self.gain = if target_gain < self.gain {
+                target_gain
+            } else {
+                self.gain + self.release_coefficient * (target_gain - self.gain)
+            };
+
+            // Rounding errors in the moving average could still let a sample slightly overshoot
+            for (channel, delay) in buffer.iter_mut().zip(&mut self.delays) {
+                let delayed = std::mem::replace(&mut delay[self.delay_pos], channel[sample_idx]);
+                channel[sample_idx] = (delayed * self.gain).clamp(-self.ceiling, self.ceiling);
+            }
+            self.delay_pos = (self.delay_pos + 1) % self.lookahead;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for delay in &mut self.delays {
+            delay.fill(0.0);
+        }
+        self.queue_len = 0;
+        self.minimums.fill(1.0);
+        self.minimum_sum = self.minimums.len() as f64;
+        self.sample_count = 0;
+        self.gain = 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    #[test]
+    fn gain_keeps_delayed_input_below_ceiling() {
+        let sample_rate = 44100.0;
+        let ceiling = 10.0f32.powf(-0.3 / 20.0);
+        for (amplitude, frequency) in [(2.0, 100.0), (3.0, 440.0), (10.0, 30.0), (1.5, 5000.0)] {
+            let mut limiter = Limiter::new(1, sample_rate, ceiling, 1.5, 50.0);
+            let lookahead = limiter.latency_samples() as usize;
+            let input: Vec<f32> = (0..sample_rate as usize)
+                .map(|idx| (idx as f32 / sample_rate * frequency * TAU).sin() * amplitude)
+                .collect();
+
+            // Processing one sample at a time exposes the gain that was applied to the delayed
+            // sample, before the final clamp
+            for (idx, sample) in input.iter().enumerate() {
+                limiter.process(&mut [&mut [*sample]]);
+                if let Some(delayed) = idx.checked_sub(lookahead).map(|idx| input[idx]) {
+                    let limited = delayed.abs() * limiter.gain;
+                    assert!(
+                        limited <= ceiling * 1.0001,
+                        "{limited} exceeds the ceiling at sample {idx} for a {frequency} Hz sine \
+                         at {amplitude}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quiet_signals_pass_through_delayed() {
+        let mut limiter = Limiter::new(2, 48000.0, 1.0, 1.5, 50.0);
+        let lookahead = limiter.latency_samples() as usize;
+        let input: Vec<f32> = (0..1000)
+            .map(|idx| (idx as f32 * 0.01).sin() * 0.5)
+            .collect();
+        let mut left = input.clone();
+        let mut right: Vec<f32> = input.iter().map(|sample| -sample).collect();
+        limiter.process(&mut [&mut left, &mut right]);
+
+        for idx in lookahead..input.len() {
+            assert!((left[idx] - input[idx - lookahead]).abs() < 1e-6);
+            assert!((right[idx] + input[idx - lookahead]).abs() < 1e-6);
+        }
+    }
+}
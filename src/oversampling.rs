@@ -0,0 +1,238 @@
+//! Polyphase half-band FIR resamplers for oversampling the band processing by a factor of two or
+//! four. Every factor of two is a separate stage with its own half-band lowpass filter.
+
+use std::f32::consts::PI;
+
+/// The maximum number of 2x stages, so the maximum oversampling factor is `2^MAX_STAGES`.
+pub const MAX_STAGES: usize = 2;
+/// The number of taps in the half-band lowpass filters. `TAPS - 1` is a multiple of
+/// `2^(MAX_STAGES + 1)` so the total latency is always a whole number of samples.
+const TAPS: usize = 33;
+/// Only the even taps of the half-band filter are used in the upsampler's polyphase branches.
+const EVEN_TAPS: usize = TAPS / 2 + 1;
+
+/// The latency in samples at the original sample rate introduced by upsampling and then
+/// downsampling again with `stages` 2x stages. Both the up- and the downsampling filter of every
+/// stage add `(TAPS - 1) / 2` samples of latency at that stage's output rate.
+pub fn latency_samples(stages: usize) -> u32 {
+    (0..stages)
+        .map(|stage| ((TAPS - 1) >> (stage + 1)) as u32)
+        .sum()
+}
+
+/// Compute the coefficients for a Blackman windowed sinc lowpass filter with its cutoff at a
+/// quarter of the sample rate, normalized to unity gain at DC.
+fn half_band_coefficients() -> [f32; TAPS] {
+    let center = (TAPS - 1) as f32 / 2.0;
+    let mut coefficients = [0.0; TAPS];
+    for (tap_idx, coefficient) in coefficients.iter_mut().enumerate() {
+        let x = tap_idx as f32 - center;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x / 2.0).sin() / (PI * x / 2.0)
+        };
+        let phase = 2.0 * PI * tap_idx as f32 / (TAPS - 1) as f32;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+        *coefficient = sinc * window;
+    }
+
+    let sum: f32 = coefficients.iter().sum();
+    for coefficient in &mut coefficients {
+        *coefficient /= sum;
+    }
+
+    coefficients
+}
+
+/// A single 2x upsampling stage.
+#[derive(Debug, Clone)]
+struct Upsampler2x {
+    coefficients: [f32; TAPS],
+    /// The most recent input samples, newest first.
+    history: [f32; EVEN_TAPS],
+}
+
+impl Default for Upsampler2x {
+    fn default() -> Self {
+        Self {
+            coefficients: half_band_coefficients(),
+            history: [0.0; EVEN_TAPS],
+        }
+    }
+}
+
+impl Upsampler2x {
+    /// Produce the two output samples for an input sample. Instead of zero stuffing the input and
+    /// filtering that, the even and odd taps are applied to the input directly.
+    fn process(&mut self, input: f32) -> [f32; 2] {
+        self.history.copy_within(..EVEN_TAPS - 1, 1);
+        self.history[0] = input;
+
+        let mut output = [0.0; 2];
+        for (phase, sample) in output.iter_mut().enumerate() {
+            *sample = self
+                .coefficients
+                .iter()
+                .skip(phase)
+                .step_by(2)
+                .zip(&self.history)
+                .map(|(coefficient, x)| coefficient * x)
+                .sum::<f32>()
+                // This compensates for the energy that would have been lost to zero stuffing
+                * 2.0;
+        }
+
+        output
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+    }
+}
+
+/// A single 2x downsampling stage.
+#[derive(Debug, Clone)]
+struct Downsampler2x {
+    coefficients: [f32; TAPS],
+    /// The most recent input samples, newest first. This holds one more sample than the filter
+    /// needs since the second sample of every pair is only used for the next output sample.
+    history: [f32; TAPS + 1],
+}
+
+impl Default for Downsampler2x {
+    fn default() -> Self {
+        Self {
+            coefficients: half_band_coefficients(),
+            history: [0.0; TAPS + 1],
+        }
+    }
+}
+
+impl Downsampler2x {
+    /// Produce a single output sample from two input samples. The filter only needs to be
+    /// evaluated for the samples that survive the decimation.
+    fn process(&mut self, input: [f32; 2]) -> f32 {
+        self.history.copy_within(..TAPS - 1, 2);
+        self.history[1] = input[0];
+        self.history[0] = input[1];
+
+        // The output is aligned with the first sample of the pair, so the decimation doesn't add
+        // another half a sample of latency
+        self.coefficients
+            .iter()
+            .zip(&self.history[1..])
+            .map(|(coefficient, x)| coefficient * x)
+            .sum()
+    }
+
+    fn reset(&mut self) {
+        self.history.fill(0.0);
+    }
+}
+
+/// Upsamples a signal by `2^stages`.
+#[derive(Debug, Clone, Default)]
+pub struct Upsampler {
+    stages: [Upsampler2x; MAX_STAGES],
+}
+
+impl Upsampler {
+    /// Upsample `input` by a factor of `2^stages` into `output`, which must contain exactly
+    /// `input.len() << stages` samples.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32], stages: usize) {
+        nih_plug::nih_debug_assert_eq!(output.len(), input.len() << stages);
+
+        for (input_sample, output_samples) in input.iter().zip(output.chunks_exact_mut(1 << stages))
+        {
+            let mut samples = [0.0; 1 << MAX_STAGES];
+            samples[0] = *input_sample;
+            for (stage_idx, stage) in self.stages[..stages].iter_mut().enumerate() {
+                let mut upsampled = [0.0; 1 << MAX_STAGES];
+                for (sample_idx, sample) in samples[..1 << stage_idx].iter().enumerate() {
+                    upsampled[sample_idx * 2..sample_idx * 2 + 2]
+                        .copy_from_slice(&stage.process(*sample));
+                }
+                samples = upsampled;
+            }
+
+            output_samples.copy_from_slice(&samples[..1 << stages]);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+}
+
+/// Downsamples a signal by `2^stages`. This is the counterpart to [`Upsampler`].
+#[derive(Debug, Clone, Default)]
+pub struct Downsampler {
+    stages: [Downsampler2x; MAX_STAGES],
+}
+
+impl Downsampler {
+    /// Downsample `input` by a factor of `2^stages` into `output`. `input` must contain exactly
+    /// `output.len() << stages` samples.
+    pub fn process(&mut self, input: &[f32], output: &mut [f32], stages: usize) {
+        nih_plug::nih_debug_assert_eq!(input.len(), output.len() << stages);
+
+        for (input_samples, output_sample) in input.chunks_exact(1 << stages).zip(output) {
+            let mut samples = [0.0; 1 << MAX_STAGES];
+            samples[..1 << stages].copy_from_slice(input_samples);
+            // The stages are applied in the opposite order from the upsampler, starting at the
+            // highest sample rate
+            for (stage_idx, stage) in self.stages[..stages].iter_mut().enumerate().rev() {
+                for sample_idx in 0..1 << stage_idx {
+                    samples[sample_idx] =
+                        stage.process([samples[sample_idx * 2], samples[sample_idx * 2 + 1]]);
+                }
+            }
+
+            *output_sample = samples[0];
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_peaks_at_the_reported_latency() {
+        const IMPULSE_IDX: usize = 10;
+        for stages in 1..=MAX_STAGES {
+            let mut input = [0.0; 128];
+            input[IMPULSE_IDX] = 1.0;
+            let mut oversampled = vec![0.0; input.len() << stages];
+            let mut output = [0.0; 128];
+            Upsampler::default().process(&input, &mut oversampled, stages);
+            Downsampler::default().process(&oversampled, &mut output, stages);
+
+            let peak_idx = (0..output.len())
+                .max_by(|&a, &b| output[a].abs().total_cmp(&output[b].abs()))
+                .unwrap();
+            assert_eq!(peak_idx - IMPULSE_IDX, latency_samples(stages) as usize);
+
+            // The half-band filters' transition band sits right below the original Nyquist
+            // frequency, so some of the impulse's energy goes missing there. The gain at DC is
+            // still unity.
+            assert!(
+                (output[peak_idx] - 1.0).abs() < 0.1,
+                "peak = {}",
+                output[peak_idx]
+            );
+            let dc_gain: f32 = output.iter().sum();
+            assert!((dc_gain - 1.0).abs() < 1e-4, "DC gain = {dc_gain}");
+        }
+    }
+}
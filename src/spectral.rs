@@ -0,0 +1,495 @@
+//! The FFT engine, an STFT based alternative to the filter bank. Both signals are split into
+//! overlapping windowed frames, and the carrier's spectrum is shaped by the modulator's spectrum
+//! before it's transformed back and overlap-added. The spectra are divided into the same bands
+//! as the filter bank, so all of the band settings still apply.
+
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use crate::filterbank::flush_denormal;
+use crate::SELF_VOCODING_ENVELOPE_FLOOR;
+
+/// The number of samples in every frame. This needs to be a power of two.
+pub const FFT_SIZE: usize = 1024;
+/// The latency in samples. A frame can only be processed once all of its samples have come in,
+/// and its first sample is output right after that. This is the same for every overlap factor.
+pub const LATENCY_SAMPLES: u32 = FFT_SIZE as u32;
+/// The number of bins from DC up to and including the Nyquist frequency.
+const NUM_BINS: usize = FFT_SIZE / 2 + 1;
+
+/// The window function the frames are multiplied with before and after the transforms. Windows
+/// with a narrower main lobe smear the spectrum less, while windows with lower side lobes leak
+/// less of every frequency into the surrounding bins.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FftWindow {
+    /// A good balance between smearing and leakage.
+    #[name = "Hann"]
+    Hann,
+    /// The narrowest main lobe, with the most leakage far away from the main lobe.
+    #[name = "Hamming"]
+    Hamming,
+    /// The least leakage, but also the most smearing.
+    #[name = "Blackman"]
+    Blackman,
+}
+
+impl FftWindow {
+    /// The periodic version of the window at `idx` in a frame of `size` samples.
+    fn value(self, idx: usize, size: usize) -> f32 {
+        let phase = 2.0 * PI * idx as f32 / size as f32;
+        match self {
+            FftWindow::Hann => 0.5 - 0.5 * phase.cos(),
+            FftWindow::Hamming => 0.54 - 0.46 * phase.cos(),
+            FftWindow::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+        }
+    }
+}
+
+/// How many frames every sample is part of. More overlap makes changes in the band levels
+/// smoother, at the cost of processing more frames.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FftOverlap {
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+    #[name = "8x"]
+    X8,
+}
+
+impl FftOverlap {
+    /// The number of samples between the starts of two consecutive frames.
+    pub fn hop_size(self) -> usize {
+        match self {
+            FftOverlap::X2 => FFT_SIZE / 2,
+            FftOverlap::X4 => FFT_SIZE / 4,
+            FftOverlap::X8 => FFT_SIZE / 8,
+        }
+    }
+}
+
+/// An in-place radix-2 complex FFT for a fixed power of two size.
+#[derive(Debug, Clone)]
+struct Fft {
+    /// `e^(-2 pi i k / N)` for the first half of the bins, as real and imaginary parts.
+    twiddles: Vec<(f32, f32)>,
+    /// Every index with its bits reversed, for the reordering before the butterflies.
+    bit_reversed: Vec<usize>,
+}
+
+impl Fft {
+    fn new(size: usize) -> Self {
+        debug_assert!(size.is_power_of_two() && size > 1);
+        let bits = size.trailing_zeros();
+
+        Self {
+            twiddles: (0..size / 2)
+                .map(|bin| {
+                    let phase = -2.0 * PI * bin as f32 / size as f32;
+                    (phase.cos(), phase.sin())
+                })
+                .collect(),
+            bit_reversed: (0..size)
+                .map(|idx| idx.reverse_bits() >> (usize::BITS - bits))
+                .collect(),
+        }
+    }
+
+    /// Transform the complex signal in `re` and `im` in place. The inverse transform is not
+    /// normalized, so a forward and an inverse transform scale the signal by the FFT size.
+    fn process(&self, re: &mut [f32], im: &mut [f32], inverse: bool) {
+        let size = re.len();
+        for (idx, reversed_idx) in self.bit_reversed.iter().enumerate() {
+            if *reversed_idx > idx {
+                re.swap(idx, *reversed_idx);
+                im.swap(idx, *reversed_idx);
+            }
+        }
+
+        let mut len = 2;
+        while len <= size {
+            let half_len = len / 2;
+            let twiddle_stride = size / len;
+            for start in (0..size).step_by(len) {
+                for k in 0..half_len {
+                    let (w_re, w_im) = self.twiddles[k * twiddle_stride];
+                    let w_im = if inverse { -w_im } else { w_im };
+                    let (a, b) = (start + k, start + k + half_len);
+
+                    let t_re = re[b] * w_re - im[b] * w_im;
+                    let t_im = re[b] * w_im + im[b] * w_re;
+                    re[b] = re[a] - t_re;
+                    im[b] = im[a] - t_im;
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+
+            len *= 2;
+        }
+    }
+}
+
+/// Settings for [`SpectralVocoder::process()`] that stay the same for an entire block.
+#[derive(Debug, Clone, Copy)]
+pub struct SpectralSettings<'a> {
+    pub sample_rate: f32,
+    /// The bands' lower and upper edges in Hz.
+    pub band_edges: &'a [(f32, f32)],
+    /// For every carrier band, the index of the modulator band whose level shapes it.
+    pub formant_map: &'a [usize],
+    pub band_gains: &'a [f32],
+    /// The one-pole coefficients the band levels rise and fall with. These are applied once per
+    /// frame instead of once per sample.
+    pub attack: f32,
+    pub release: f32,
+    /// Whether the carrier is the modulator itself. The carrier bands are then divided by their
+    /// own levels first, so the band levels aren't applied twice.
+    pub self_vocoding: bool,
+}
+
+/// The STFT state for a single channel.
+#[derive(Debug, Clone)]
+pub struct SpectralVocoder {
+    fft: Fft,
+    /// The window function `analysis_window` and `synthesis_window` were computed for.
+    window_function: FftWindow,
+    /// The number of samples between two frames, which `synthesis_window` was computed for.
+    hop_size: usize,
+    /// The window the frames are multiplied with before the forward transform.
+    analysis_window: Vec<f32>,
+    /// The window the frames are multiplied with after the inverse transform. This is the
+    /// analysis window divided by the sum of the squared analysis windows of all frames
+    /// overlapping a sample, so the overlap-add reconstructs an unshaped carrier exactly for any
+    /// window function.
+    synthesis_window: Vec<f32>,
+    /// Turns a band's summed bin power into the amplitude of a sine wave with the same power, so
+    /// the band levels match the filter bank's envelopes.
+    level_scale: f32,
+
+    /// The last [`FFT_SIZE`] modulator and carrier samples. These are ring buffers starting at
+    /// `input_pos`.
+    modulator_input: Vec<f32>,
+    carrier_input: Vec<f32>,
+    input_pos: usize,
+    /// The overlap-added output. The first `hop_size` samples are output while the next frame's
+    /// samples come in.
+    output: Vec<f32>,
+    /// The number of samples that came in since the last frame.
+    hop_pos: usize,
+
+    // The FFT's buffers. The modulator and the carrier are transformed together, and the
+    // modulator's power and the carrier's spectrum are then split off into their own buffers.
+    fft_re: Vec<f32>,
+    fft_im: Vec<f32>,
+    modulator_power: Vec<f32>,
+    carrier_re: Vec<f32>,
+    carrier_im: Vec<f32>,
+    /// The gain every bin of the carrier is scaled by in the current frame.
+    bin_gains: Vec<f32>,
+}
+
+impl Default for SpectralVocoder {
+    fn default() -> Self {
+        let mut spectral_vocoder = Self {
+            fft: Fft::new(FFT_SIZE),
+            window_function: FftWindow::Hann,
+            hop_size: FftOverlap::X4.hop_size(),
+            analysis_window: vec![0.0; FFT_SIZE],
+            synthesis_window: vec![0.0; FFT_SIZE],
+            level_scale: 0.0,
+
+            modulator_input: vec![0.0; FFT_SIZE],
+            carrier_input: vec![0.0; FFT_SIZE],
+            input_pos: 0,
+            output: vec![0.0; FFT_SIZE],
+            hop_pos: 0,
+
+            fft_re: vec![0.0; FFT_SIZE],
+            fft_im: vec![0.0; FFT_SIZE],
+            modulator_power: vec![0.0; NUM_BINS],
+            carrier_re: vec![0.0; NUM_BINS],
+            carrier_im: vec![0.0; NUM_BINS],
+            bin_gains: vec![0.0; NUM_BINS],
+        };
+        spectral_vocoder.compute_windows();
+
+        spectral_vocoder
+    }
+}
+
+impl SpectralVocoder {
+    /// Switch to a different window function. This doesn't allocate, so it can be called from the
+    /// audio thread.
+    pub fn set_window(&mut self, window_function: FftWindow) {
+        if window_function != self.window_function {
+            self.window_function = window_function;
+            self.compute_windows();
+        }
+    }
+
+    /// Switch to a different overlap factor. This doesn't allocate either, but the frames that
+    /// are still being overlap-added were spaced for the old overlap, so the output is cleared
+    /// and drops out for one frame.
+    pub fn set_overlap(&mut self, overlap: FftOverlap) {
+        if overlap.hop_size() != self.hop_size {
+            self.hop_size = overlap.hop_size();
+            self.compute_windows();
+            self.output.fill(0.0);
+            self.hop_pos = 0;
+        }
+    }
+
+    /// Vocode `carrier` with `modulator` and write the result to `output`. All three need to be
+    /// the same length. `band_amplitudes` holds the modulator bands' levels, which are updated
+    /// every frame.
+    pub fn process(
+        &mut self,
+        modulator: &[f32],
+        carrier: &[f32],
+        output: &mut [f32],
+        band_amplitudes: &mut [f32],
+        settings: SpectralSettings,
+    ) {
+        nih_plug::nih_debug_assert_eq!(modulator.len(), output.len());
+        nih_plug::nih_debug_assert_eq!(carrier.len(), output.len());
+
+        for ((modulator_sample, carrier_sample), output_sample) in
+            modulator.iter().zip(carrier).zip(output)
+        {
+            *output_sample = self.output[self.hop_pos];
+
+            self.modulator_input[self.input_pos] = *modulator_sample;
+            self.carrier_input[self.input_pos] = *carrier_sample;
+            self.input_pos = (self.input_pos + 1) % FFT_SIZE;
+
+            self.hop_pos += 1;
+            if self.hop_pos == self.hop_size {
+                self.hop_pos = 0;
+                self.output.copy_within(self.hop_size.., 0);
+                self.output[FFT_SIZE - self.hop_size..].fill(0.0);
+                self.process_frame(band_amplitudes, settings);
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.modulator_input.fill(0.0);
+        self.carrier_input.fill(0.0);
+        self.input_pos = 0;
+        self.output.fill(0.0);
+        self.hop_pos = 0;
+    }
+
+    /// Compute the analysis and synthesis windows and the level scale for the current window
+    /// function and hop size.
+    fn compute_windows(&mut self) {
+        for (idx, window) in self.analysis_window.iter_mut().enumerate() {
+            *window = self.window_function.value(idx, FFT_SIZE);
+        }
+
+        for (idx, synthesis_window) in self.synthesis_window.iter_mut().enumerate() {
+            let overlap_power: f32 = (idx % self.hop_size..FFT_SIZE)
+                .step_by(self.hop_size)
+                .map(|overlapping_idx| self.analysis_window[overlapping_idx].powi(2))
+                .sum();
+            *synthesis_window = self.analysis_window[idx] / overlap_power;
+        }
+
+        // Half of a real signal's power is in the negative frequencies, and a sine wave's
+        // amplitude is its RMS level times the square root of two
+        let window_power: f32 = self.analysis_window.iter().map(|w| w * w).sum();
+        self.level_scale = 4.0 / (FFT_SIZE as f32 * window_power);
+    }
+
+    /// Vocode the last [`FFT_SIZE`] samples and add the result to the output.
+    fn process_frame(&mut self, band_amplitudes: &mut [f32], settings: SpectralSettings) {
+        // Both signals are real, so they can be transformed at once as the real and imaginary
+        // parts of a single complex signal
+        for (idx, window) in self.analysis_window.iter().enumerate() {
+            let input_idx = (self.input_pos + idx) % FFT_SIZE;
+            self.fft_re[idx] = self.modulator_input[input_idx] * window;
+            self.fft_im[idx] = self.carrier_input[input_idx] * window;
+        }
+        self.fft.process(&mut self.fft_re, &mut self.fft_im, false);
+
+        for bin in 0..NUM_BINS {
+            let mirrored_bin = (FFT_SIZE - bin) % FFT_SIZE;
+            let (re, im) = (self.fft_re[bin], self.fft_im[bin]);
+            let (mirrored_re, mirrored_im) = (self.fft_re[mirrored_bin], self.fft_im[mirrored_bin]);
+
+            let modulator_re = (re + mirrored_re) * 0.5;
+            let modulator_im = (im - mirrored_im) * 0.5;
+            self.modulator_power[bin] = modulator_re * modulator_re + modulator_im * modulator_im;
+            self.carrier_re[bin] = (im + mirrored_im) * 0.5;
+            self.carrier_im[bin] = (mirrored_re - re) * 0.5;
+        }
+
+        let bin_width = settings.sample_rate / FFT_SIZE as f32;
+        for ((low, high), amplitude) in settings.band_edges.iter().zip(band_amplitudes.iter_mut()) {
+            let level = (self.modulator_power[analysis_bins(*low, *high, bin_width)]
+                .iter()
+                .sum::<f32>()
+                * self.level_scale)
+                .sqrt();
+            let coefficient = if level > *amplitude {
+                settings.attack
+            } else {
+                settings.release
+            };
+
+            *amplitude = flush_denormal(*amplitude + coefficient * (level - *amplitude));
+        }
+
+        // Bins outside of the band range are removed, just like the filter bank would
+        self.bin_gains.fill(0.0);
+        for (band_idx, (low, high)) in settings.band_edges.iter().enumerate() {
+            let mut gain =
+                band_amplitudes[settings.formant_map[band_idx]] * settings.band_gains[band_idx];
+            if settings.self_vocoding {
+                let carrier_power: f32 = self.carrier_re[analysis_bins(*low, *high, bin_width)]
+                    .iter()
+                    .zip(&self.carrier_im[analysis_bins(*low, *high, bin_width)])
+                    .map(|(re, im)| re * re + im * im)
+                    .sum();
+                gain /= (carrier_power * self.level_scale)
+                    .sqrt()
+                    .max(SELF_VOCODING_ENVELOPE_FLOOR);
+            }
+
+            self.bin_gains[band_bins(*low, *high, bin_width)].fill(gain);
+        }
+
+        // The shaped spectrum is still the spectrum of a real signal, so the negative frequencies
+        // mirror the positive ones
+        for bin in 0..NUM_BINS {
+            let gain = self.bin_gains[bin];
+            self.fft_re[bin] = self.carrier_re[bin] * gain;
+            self.fft_im[bin] = self.carrier_im[bin] * gain;
+        }
+        for bin in 1..NUM_BINS - 1 {
+            self.fft_re[FFT_SIZE - bin] = self.fft_re[bin];
+            self.fft_im[FFT_SIZE - bin] = -self.fft_im[bin];
+        }
+        self.fft.process(&mut self.fft_re, &mut self.fft_im, true);
+
+        for ((output_sample, re), window) in self
+            .output
+            .iter_mut()
+            .zip(&self.fft_re)
+            .zip(&self.synthesis_window)
+        {
+            *output_sample += re * window / FFT_SIZE as f32;
+        }
+    }
+}
+
+/// The bins whose center frequencies fall inside the band from `low` to `high` Hz. Bands that are
+/// narrower than a bin may not contain any bins at all, so those don't pass any carrier.
+fn band_bins(low: f32, high: f32, bin_width: f32) -> Range<usize> {
+    let first_bin = ((low / bin_width).ceil() as usize).min(NUM_BINS);
+    let end_bin = ((high / bin_width).ceil() as usize).min(NUM_BINS);
+
+    first_bin..end_bin.max(first_bin)
+}
+
+/// The bins that are measured for the band from `low` to `high` Hz. This is [`band_bins()`], or
+/// the bin closest to the band's center frequency if the band doesn't contain any bins.
+fn analysis_bins(low: f32, high: f32, bin_width: f32) -> Range<usize> {
+    let bins = band_bins(low, high, bin_width);
+    if bins.is_empty() {
+        let center_bin = (((low * high).sqrt() / bin_width).round() as usize).min(NUM_BINS - 1);
+        center_bin..center_bin + 1
+    } else {
+        bins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    /// Vocode a carrier made of a few sines with a steady full scale sine as the modulator, using
+    /// a single band that covers the whole spectrum. Returns the largest difference between the
+    /// output and the carrier delayed by [`LATENCY_SAMPLES`] and scaled by the band's level, once
+    /// the first frames have filled up, along with that level.
+    fn passthrough_error(spectral_vocoder: &mut SpectralVocoder) -> (f32, f32) {
+        let num_samples = FFT_SIZE * 12;
+        let modulator: Vec<f32> = (0..num_samples)
+            .map(|idx| (2.0 * PI * 1000.0 * idx as f32 / SAMPLE_RATE).sin())
+            .collect();
+        let carrier: Vec<f32> = (0..num_samples)
+            .map(|idx| {
+                let time = idx as f32 / SAMPLE_RATE;
+                [(220.0, 0.3), (1234.5, 0.2), (5000.0, 0.1)]
+                    .iter()
+                    .map(|(frequency, amplitude)| (2.0 * PI * frequency * time).sin() * amplitude)
+                    .sum()
+            })
+            .collect();
+
+        let mut output = vec![0.0; num_samples];
+        let mut band_amplitudes = [0.0];
+        spectral_vocoder.process(
+            &modulator,
+            &carrier,
+            &mut output,
+            &mut band_amplitudes,
+            SpectralSettings {
+                sample_rate: SAMPLE_RATE,
+                band_edges: &[(0.0, SAMPLE_RATE / 2.0)],
+                formant_map: &[0],
+                band_gains: &[1.0],
+                attack: 1.0,
+                release: 1.0,
+                self_vocoding: false,
+            },
+        );
+
+        let latency = LATENCY_SAMPLES as usize;
+        let error = (FFT_SIZE * 4..num_samples)
+            .map(|idx| (output[idx] - carrier[idx - latency] * band_amplitudes[0]).abs())
+            .fold(0.0, f32::max);
+
+        (error, band_amplitudes[0])
+    }
+
+    #[test]
+    fn overlap_add_reconstructs_the_carrier_for_every_window() {
+        for window in [FftWindow::Hann, FftWindow::Hamming, FftWindow::Blackman] {
+            let mut spectral_vocoder = SpectralVocoder::default();
+            spectral_vocoder.set_window(window);
+
+            let (error, level) = passthrough_error(&mut spectral_vocoder);
+            assert!(
+                (level - 1.0).abs() < 1e-3,
+                "{window:?} measures a level of {level}"
+            );
+            assert!(error < 2e-4, "{window:?} is off by {error}");
+        }
+    }
+
+    #[test]
+    fn overlap_add_reconstructs_the_carrier_for_every_overlap() {
+        for overlap in [FftOverlap::X2, FftOverlap::X4, FftOverlap::X8] {
+            for window in [FftWindow::Hann, FftWindow::Hamming, FftWindow::Blackman] {
+                let mut spectral_vocoder = SpectralVocoder::default();
+                spectral_vocoder.set_overlap(overlap);
+                spectral_vocoder.set_window(window);
+
+                let (error, level) = passthrough_error(&mut spectral_vocoder);
+                assert!(
+                    (level - 1.0).abs() < 1e-3,
+                    "{overlap:?} measures a level of {level}"
+                );
+                assert!(
+                    error < 2e-4,
+                    "{overlap:?} with {window:?} is off by {error}"
+                );
+            }
+        }
+    }
+}
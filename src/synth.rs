@@ -0,0 +1,275 @@
+//! A small polyphonic oscillator bank that can be played over MIDI and used as the vocoder's
+//! carrier in place of the sidechain input.
+
+use nih_plug::util;
+use std::f32::consts::PI;
+
+/// The maximum number of simultaneously playing voices.
+pub const MAX_VOICES: usize = 16;
+/// The number of voice slots. Every voice can be fading out after being stolen while a new voice
+/// is already playing in its place, so there are twice as many slots as voices.
+const NUM_VOICE_SLOTS: usize = MAX_VOICES * 2;
+/// How long a stolen voice takes to fade out.
+const STEAL_FADE_MS: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Voice {
+    /// The MIDI note this voice is playing, or `None` if the voice is free. Voices stay in use
+    /// until they've fully faded out after their note off.
+    note: Option<u8>,
+    velocity: f32,
+    /// Whether the voice's note has been released.
+    released: bool,
+    /// Whether the voice has been stolen for a new note. It then quickly fades out, and it no
+    /// longer counts towards the voice limit.
+    stolen: bool,
+    /// When the voice's note started, counted in notes. Lower values are older.
+    age: u64,
+    /// The amplitude envelope's current level, between 0 and 1.
+    envelope: f32,
+    /// The oscillator's phase in `[0, 1)`.
+    phase: f32,
+    /// How much `phase` advances every sample.
+    phase_delta: f32,
+}
+
+/// A polyphonic band-limited sawtooth synth. Sawtooths contain every harmonic, which makes them a
+/// good carrier for a vocoder.
+#[derive(Debug)]
+pub struct CarrierSynth {
+    sample_rate: f32,
+    voices: [Voice; NUM_VOICE_SLOTS],
+    /// The number of voices that may play at the same time, up to [`MAX_VOICES`].
+    max_voices: usize,
+    /// The age the next note's voice gets.
+    next_age: u64,
+    /// How much the voices' envelopes rise every sample after a note on, and how much they fall
+    /// every sample after a note off or after being stolen. The envelopes are linear ramps.
+    attack_step: f32,
+    release_step: f32,
+    steal_step: f32,
+}
+
+impl Default for CarrierSynth {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            voices: [Voice::default(); NUM_VOICE_SLOTS],
+            max_voices: MAX_VOICES,
+            next_age: 0,
+            attack_step: 1.0,
+            release_step: 1.0,
+            steal_step: 1.0,
+        }
+    }
+}
+
+impl CarrierSynth {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.steal_step = 1.0 / (STEAL_FADE_MS / 1000.0 * sample_rate).max(1.0);
+    }
+
+    /// Limit the number of voices that can play at the same time. Lowering the limit below the
+    /// number of currently playing voices steals the excess voices right away.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.clamp(1, MAX_VOICES);
+        while self.num_active_voices() > self.max_voices {
+            self.steal_voice();
+        }
+    }
+
+    /// Set the time in milliseconds it takes the voices to fade in after a note on, and to fade
+    /// out after a note off.
+    pub fn set_envelope(&mut self, attack_ms: f32, release_ms: f32) {
+        let ms_to_step = |time_ms: f32| 1.0 / (time_ms / 1000.0 * self.sample_rate).max(1.0);
+        self.attack_step = ms_to_step(attack_ms);
+        self.release_step = ms_to_step(release_ms);
+    }
+
+    /// Start playing a note. If the voice limit has been reached, a voice is stolen for the new
+    /// note.
+    pub fn note_on(&mut self, note: u8, velocity: f32) {
+        if self.num_active_voices() >= self.max_voices {
+            self.steal_voice();
+        }
+
+        // Normally there's always a free slot. Should a burst of notes ever use up all of them,
+        // the quietest stolen voice is cut off instead.
+        let voice = match self.voices.iter().position(|voice| voice.note.is_none()) {
+            Some(voice_idx) => &mut self.voices[voice_idx],
+            None => self
+                .voices
+                .iter_mut()
+                .filter(|voice| voice.stolen)
+                .min_by(|a, b| a.level().total_cmp(&b.level()))
+                .expect("The voice limit leaves at least one stolen voice"),
+        };
+        *voice = Voice {
+            note: Some(note),
+            velocity,
+            released: false,
+            stolen: false,
+            age: self.next_age,
+            envelope: 0.0,
+            phase: 0.0,
+            phase_delta: util::midi_note_to_freq(note) / self.sample_rate,
+        };
+        self.next_age += 1;
+    }
+
+    /// Release all voices playing `note`. They keep playing until their envelopes have faded out.
+    pub fn note_off(&mut self, note: u8) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|voice| voice.note == Some(note))
+        {
+            voice.released = true;
+        }
+    }
+
+    /// Stop all voices.
+    pub fn reset(&mut self) {
+        self.voices = [Voice::default(); NUM_VOICE_SLOTS];
+    }
+
+    /// The number of voices that count towards the voice limit. Released voices still count
+    /// until they've faded out, stolen voices don't.
+    fn num_active_voices(&self) -> usize {
+        self.voices
+            .iter()
+            .filter(|voice| voice.note.is_some() && !voice.stolen)
+            .count()
+    }
+
+    /// Fade out a voice to make room for a new one. Released voices are already on their way out,
+    /// so the quietest of those is picked first. If all voices are still held, the oldest one is
+    /// stolen.
+    fn steal_voice(&mut self) {
+        let victim = self
+            .voices
+            .iter_mut()
+            .filter(|voice| voice.note.is_some() && !voice.stolen)
+            .min_by(|a, b| {
+                b.released.cmp(&a.released).then_with(|| {
+                    if a.released {
+                        a.level().total_cmp(&b.level())
+                    } else {
+                        a.age.cmp(&b.age)
+                    }
+                })
+            });
+        if let Some(victim) = victim {
+            victim.stolen = true;
+        }
+    }
+
+    /// Compute the synth's next output sample.
+    pub fn next_sample(&mut self) -> f32 {
+        let mut output = 0.0;
+        for voice in self.voices.iter_mut().filter(|voice| voice.note.is_some()) {
+            if voice.stolen {
+                voice.envelope -= self.steal_step;
+                if voice.envelope <= 0.0 {
+                    voice.note = None;
+                    continue;
+                }
+            } else if voice.released {
+                voice.envelope -= self.release_step;
+                if voice.envelope <= 0.0 {
+                    voice.note = None;
+                    continue;
+                }
+            } else {
+                voice.envelope = (voice.envelope + self.attack_step).min(1.0);
+            }
+
+            let naive_saw = 2.0 * voice.phase - 1.0;
+            output += voice.velocity
+                * voice.envelope
+                * (naive_saw - poly_blep(voice.phase, voice.phase_delta));
+
+            voice.phase += voice.phase_delta;
+            if voice.phase >= 1.0 {
+                voice.phase -= 1.0;
+            }
+        }
+
+        output
+    }
+}
+
+impl Voice {
+    /// The voice's current output level.
+    fn level(&self) -> f32 {
+        self.velocity * self.envelope
+    }
+}
+
+/// The PolyBLEP residual for a discontinuity at phase 0. Subtracting this from a naive sawtooth
+/// band-limits the sawtooth's reset.
+fn poly_blep(phase: f32, phase_delta: f32) -> f32 {
+    if phase < phase_delta {
+        let t = phase / phase_delta;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - phase_delta {
+        let t = (phase - 1.0) / phase_delta;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// A band-limited impulse train at a single fixed pitch, used as the carrier for the robot voice.
+/// An impulse train has every harmonic at the same level, which gives the classic buzzy,
+/// monotone robot sound.
+#[derive(Debug)]
+pub struct BuzzOscillator {
+    sample_rate: f32,
+    /// The oscillator's phase in `[0, 1)`.
+    phase: f32,
+}
+
+impl Default for BuzzOscillator {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl BuzzOscillator {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Compute the next output sample at `frequency` Hz. This sums all harmonics below the
+    /// Nyquist frequency in closed form, and the result is scaled to roughly the RMS level of a
+    /// full scale sawtooth regardless of the number of harmonics.
+    pub fn next_sample(&mut self, frequency: f32) -> f32 {
+        let phase_delta = frequency / self.sample_rate;
+        let num_harmonics = (0.5 / phase_delta).floor().max(1.0);
+
+        // This is the Dirichlet kernel, the sum of `cos(2 * PI * k * phase)` for `k` from 1 to
+        // `num_harmonics`. It's equal to `num_harmonics` wherever all harmonics line up.
+        let denominator = (PI * self.phase).sin();
+        let harmonics_sum = if denominator.abs() < 1e-6 {
+            num_harmonics
+        } else {
+            ((2.0 * num_harmonics + 1.0) * PI * self.phase).sin() / (2.0 * denominator) - 0.5
+        };
+
+        self.phase += phase_delta;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+
+        harmonics_sum * (2.0 / num_harmonics).sqrt() / 3.0f32.sqrt()
+    }
+}
@@ -0,0 +1,127 @@
+//! SysEx messages for controlling the bands from external scripts and controllers.
+//!
+//! Every message starts with `F0 7D`, where `7D` is the manufacturer ID reserved for
+//! non-commercial use, followed by a command byte. All other bytes are 7-bit values.
+//!
+//! - `F0 7D 01 <first band MSB> <first band LSB> <gain>... F7` sets the gains of consecutive
+//!   bands, starting at the zero-indexed band `first band MSB * 128 + first band LSB`. Every gain
+//!   is a value from 0 to 127, where 0 mutes the band, 64 is unity gain, and 127 is just under
+//!   +6 dB. The message can hold up to [`MAX_BANDS`] gains. These gains are applied on top of the
+//!   band gain parameters.
+//! - `F0 7D 02 <freeze> F7` overrides the freeze parameter. 0 hands control back to the
+//!   parameter, 1 freezes the envelopes, and 2 unfreezes them.
+//! - `F0 7D 03 F7` clears all band gains and the freeze override.
+
+use nih_plug::prelude::SysExMessage;
+
+use crate::filterbank::MAX_BANDS;
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+/// The manufacturer ID for non-commercial use.
+const MANUFACTURER_ID: u8 = 0x7D;
+
+const COMMAND_BAND_GAINS: u8 = 0x01;
+const COMMAND_FREEZE: u8 = 0x02;
+const COMMAND_CLEAR: u8 = 0x03;
+
+/// The size of the longest message, a band gains message for every band.
+const MAX_MESSAGE_LEN: usize = 6 + MAX_BANDS;
+/// The gain value that corresponds to unity gain.
+const UNITY_GAIN_VALUE: f32 = 64.0;
+
+/// A decoded band control message. See the module documentation for the byte format.
+// Messages are decoded on the audio thread, so the gains can't be boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum BandControlMessage {
+    /// Set the gains for `num_bands` bands starting at `first_band`. Only the first `num_bands`
+    /// values in `gains` are used, and these are the raw 7-bit values from the message.
+    BandGains {
+        first_band: usize,
+        num_bands: usize,
+        gains: [u8; MAX_BANDS],
+    },
+    /// Freeze or unfreeze the envelopes regardless of the freeze parameter, or follow the
+    /// parameter again when this is `None`.
+    Freeze(Option<bool>),
+    /// Clear all band gains and the freeze override.
+    ClearOverrides,
+}
+
+impl BandControlMessage {
+    /// Convert a 7-bit gain value from a band gains message to a linear gain.
+    pub fn gain_from_value(value: u8) -> f32 {
+        f32::from(value) / UNITY_GAIN_VALUE
+    }
+}
+
+impl SysExMessage for BandControlMessage {
+    type Buffer = [u8; MAX_MESSAGE_LEN];
+
+    fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        let [SYSEX_START, MANUFACTURER_ID, command, payload @ .., SYSEX_END] = buffer else {
+            return None;
+        };
+        if payload.iter().any(|byte| byte & 0x80 != 0) {
+            return None;
+        }
+
+        match (*command, payload) {
+            (COMMAND_BAND_GAINS, [first_band_msb, first_band_lsb, values @ ..]) => {
+                let first_band = usize::from(*first_band_msb) << 7 | usize::from(*first_band_lsb);
+                if values.is_empty() || first_band + values.len() > MAX_BANDS {
+                    return None;
+                }
+
+                let mut gains = [0; MAX_BANDS];
+                gains[..values.len()].copy_from_slice(values);
+
+                Some(BandControlMessage::BandGains {
+                    first_band,
+                    num_bands: values.len(),
+                    gains,
+                })
+            }
+            (COMMAND_FREEZE, [0]) => Some(BandControlMessage::Freeze(None)),
+            (COMMAND_FREEZE, [1]) => Some(BandControlMessage::Freeze(Some(true))),
+            (COMMAND_FREEZE, [2]) => Some(BandControlMessage::Freeze(Some(false))),
+            (COMMAND_CLEAR, []) => Some(BandControlMessage::ClearOverrides),
+            _ => None,
+        }
+    }
+
+    fn to_buffer(self) -> (Self::Buffer, usize) {
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+        buffer[..2].copy_from_slice(&[SYSEX_START, MANUFACTURER_ID]);
+        let payload_len = match self {
+            BandControlMessage::BandGains {
+                first_band,
+                num_bands,
+                gains,
+            } => {
+                buffer[2] = COMMAND_BAND_GAINS;
+                buffer[3] = (first_band >> 7) as u8 & 0x7F;
+                buffer[4] = first_band as u8 & 0x7F;
+                buffer[5..5 + num_bands].copy_from_slice(&gains[..num_bands]);
+                2 + num_bands
+            }
+            BandControlMessage::Freeze(freeze) => {
+                buffer[2] = COMMAND_FREEZE;
+                buffer[3] = match freeze {
+                    None => 0,
+                    Some(true) => 1,
+                    Some(false) => 2,
+                };
+                1
+            }
+            BandControlMessage::ClearOverrides => {
+                buffer[2] = COMMAND_CLEAR;
+                0
+            }
+        };
+        buffer[3 + payload_len] = SYSEX_END;
+
+        (buffer, 4 + payload_len)
+    }
+}